@@ -1,13 +1,26 @@
-use crate::read_lines;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
 
-enum Data {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Data {
     Literal(usize),  Sum, Product, Min, Max, Greater, Less, Equal
 }
 
-struct Packet {
-    version: usize,
-    data: Data,
-    sub_packets: Vec<Packet>
+impl Data {
+    /// The 3-bit type id used for this kind of packet.
+    fn type_id(&self) -> usize {
+	use Data::*;
+	match self {
+	    Sum => 0, Product => 1, Min => 2, Max => 3, Literal(_) => 4, Greater => 5, Less => 6, Equal => 7
+	}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub version: usize,
+    pub data: Data,
+    pub sub_packets: Vec<Packet>
 }
 
 impl Packet {
@@ -21,11 +34,11 @@ impl Packet {
 	};
 	Packet { version: v, data: o, sub_packets: vec![] }
     }
-    fn sum_versions(&self) -> usize {
+    pub fn sum_versions(&self) -> usize {
 	self.version + self.sub_packets.iter().map(|x| x.sum_versions()).sum::<usize>()
     }
 
-    fn interp(&self) -> usize {
+    pub fn interp(&self) -> usize {
 	use Data::*;
 	let vals: Vec<usize> = self.sub_packets.iter().map(|x| x.interp()).collect();
 	match self.data {
@@ -53,85 +66,335 @@ impl Packet {
 	    },
 	}
     }
+
+    /// Encode the packet as a sequence of b'0' and b'1', without trailing padding.
+    ///
+    /// Panics if a version doesn't fit in 3 bits, or if an operator has 2048
+    /// or more sub-packets that don't fit in 2^15 - 1 bits.
+    pub fn to_binary(&self) -> Vec<u8> {
+	let mut bits = vec![];
+	self.encode(&mut bits);
+	bits
+    }
+
+    /// Encode the packet as a hex transmission, padding with zeros to a whole digit.
+    pub fn to_hex(&self) -> String {
+	let mut bits = self.to_binary();
+	while !bits.len().is_multiple_of(4) {
+	    bits.push(b'0');
+	}
+	bits.chunks(4).map(|c| format!("{:X}", bin(c))).collect()
+    }
+
+    fn encode(&self, bits: &mut Vec<u8>) {
+	assert!(self.version < 1 << 3, "version {} doesn't fit in 3 bits", self.version);
+	push_bits(bits, self.version, 3);
+	push_bits(bits, self.data.type_id(), 3);
+	if let Data::Literal(x) = self.data {
+	    // 4-bit groups, most significant first
+	    let mut groups = vec![x & 0xf];
+	    let mut rest = x >> 4;
+	    while rest > 0 {
+		groups.push(rest & 0xf);
+		rest >>= 4;
+	    }
+	    for (i, g) in groups.iter().enumerate().rev() {
+		push_bits(bits, usize::from(i > 0), 1);
+		push_bits(bits, *g, 4);
+	    }
+	} else {
+	    // use the sub-packet count form of the length when it fits, and the bit length otherwise
+	    if self.sub_packets.len() < 1 << 11 {
+		push_bits(bits, 1, 1);
+		push_bits(bits, self.sub_packets.len(), 11);
+		for p in &self.sub_packets {
+		    p.encode(bits);
+		}
+	    } else {
+		let mut sub = vec![];
+		for p in &self.sub_packets {
+		    p.encode(&mut sub);
+		}
+		assert!(sub.len() < 1 << 15, "sub-packets too long to encode");
+		push_bits(bits, 0, 1);
+		push_bits(bits, sub.len(), 15);
+		bits.extend(sub);
+	    }
+	}
+    }
+}
+
+fn push_bits(bits: &mut Vec<u8>, x: usize, n: usize) {
+    for i in (0..n).rev() {
+	bits.push(if (x >> i) & 1 == 1 { b'1' } else { b'0' });
+    }
+}
+
+/// Compile an expression like `sum(1, product(2, 3), lt(5, 7))` into a packet.
+///
+/// The operators are `sum`, `product`, `min`, `max`, `gt`, `lt` and `eq`,
+/// and every packet is given version 0.
+pub fn compile(src: &str) -> Result<Packet, String> {
+    let mut c = Compiler { src: src.as_bytes(), pos: 0 };
+    let packet = c.expr()?;
+    if let Some(x) = c.peek() {
+	return Err(format!("unexpected '{}' at {}", x as char, c.pos));
+    }
+    Ok(packet)
+}
+
+struct Compiler<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Compiler<'_> {
+    /// Return the next non-whitespace character, without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+	while self.pos < self.src.len() && self.src[self.pos].is_ascii_whitespace() {
+	    self.pos += 1;
+	}
+	self.src.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+	match self.peek() {
+	    Some(x) if x == c => {
+		self.pos += 1;
+		Ok(())
+	    },
+	    Some(x) => Err(format!("expected '{}' at {}, found '{}'", c as char, self.pos, x as char)),
+	    None => Err(format!("expected '{}' at end of input", c as char)),
+	}
+    }
+
+    /// Consume the longest run of characters matching `f`.
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &str {
+	let start = self.pos;
+	while self.pos < self.src.len() && f(self.src[self.pos]) {
+	    self.pos += 1;
+	}
+	std::str::from_utf8(&self.src[start..self.pos]).unwrap()
+    }
+
+    fn expr(&mut self) -> Result<Packet, String> {
+	use Data::*;
+	let next = self.peek();
+	let start = self.pos;
+	match next {
+	    Some(b'0'..=b'9') => {
+		let lit = self.take_while(|x| x.is_ascii_digit());
+		let x = lit.parse().map_err(|_| format!("literal {} at {} is too large", lit, start))?;
+		Ok(Packet::literal(0, x))
+	    },
+	    Some(x) if x.is_ascii_alphabetic() => {
+		let name = self.take_while(|x| x.is_ascii_alphanumeric());
+		let (data, binary) = match name {
+		    "sum" => (Sum, false),
+		    "product" => (Product, false),
+		    "min" => (Min, false),
+		    "max" => (Max, false),
+		    "gt" => (Greater, true),
+		    "lt" => (Less, true),
+		    "eq" => (Equal, true),
+		    _ => { return Err(format!("unknown operator '{}' at {}", name, start)); }
+		};
+		self.expect(b'(')?;
+		let mut sub_packets = vec![self.expr()?];
+		while self.peek() == Some(b',') {
+		    self.pos += 1;
+		    sub_packets.push(self.expr()?);
+		}
+		self.expect(b')')?;
+		if binary && sub_packets.len() != 2 {
+		    return Err(format!("operator at {} takes 2 arguments, found {}", start, sub_packets.len()));
+		}
+		Ok(Packet { version: 0, data, sub_packets })
+	    },
+	    Some(x) => Err(format!("unexpected '{}' at {}", x as char, self.pos)),
+	    None => Err("unexpected end of input".to_string()),
+	}
+    }
 }
 
 fn bin(x: &[u8]) -> usize {
     usize::from_str_radix(&String::from_utf8_lossy(x), 2).unwrap()
 }
-fn hex(x: &[u8]) -> usize {
-    usize::from_str_radix(&String::from_utf8_lossy(x), 16).unwrap()
+/// Bits of a hex transmission, pulled one digit at a time from a reader.
+struct BitReader<R: Read> {
+    bytes: io::Bytes<BufReader<R>>,
+    peeked: Option<u8>,
+    digit: usize,
+    // bits of `digit` not yet consumed
+    left: usize,
+    // zero digits already taken from the reader but not yet consumed
+    zeros: usize,
+    // total bits consumed
+    pos: usize,
 }
 
-fn parse_packet(x: &[u8]) -> Option<(Packet, usize, &[u8])> {
-    let version = bin(&x[..3]);
-    let id = bin(&x[3..6]);
-    // println!("{}, {}", version, id);
-    if id == 4 {
-	let mut r = 6;
-	let mut lit = 0;
-	while x[r] == b'1' {
-	    lit = lit * 16 + bin(&x[r+1..=r+4]);
-	    r += 5;
-	}
-	lit = lit * 16 + bin(&x[r+1..=r+4]);
-	r += 5;
-	return Some((Packet::literal(version, lit), r, &x[r..]));
-    }
-
-    if id != 4 {
-	let type_id = x[6] - b'0';
-
-	// println!("op {}", type_id);
-	if type_id == 0 {
-	    let total_len = bin(&x[7..22]);
-	    //println!("bit len {}", total_len);
-	    // length of subpackets
-	    let mut l: usize = 0;
-	    let mut next = &x[22..];
-	    let mut packet = Packet::op(version, id);
-	    while l < total_len {
-		let (p, len, n) = parse_packet(next).unwrap();
-		packet.sub_packets.push(p);
-		next = n;
-		l += len;
+impl<R: Read> BitReader<R> {
+    fn new(r: R) -> Self {
+	BitReader { bytes: BufReader::new(r).bytes(), peeked: None, digit: 0, left: 0, zeros: 0, pos: 0 }
+    }
+
+    fn peek_byte(&mut self) -> io::Result<Option<u8>> {
+	if self.peeked.is_none() {
+	    self.peeked = self.bytes.next().transpose()?;
+	}
+	Ok(self.peeked)
+    }
+
+    /// Read the next `n` bits as a number.
+    fn bits(&mut self, n: usize) -> io::Result<usize> {
+	let mut x = 0;
+	for _ in 0..n {
+	    if self.left == 0 && self.zeros > 0 {
+		self.zeros -= 1;
+		self.digit = 0;
+		self.left = 4;
+	    } else if self.left == 0 {
+		self.digit = match self.peek_byte()? {
+		    Some(c) if c.is_ascii_hexdigit() => (c as char).to_digit(16).unwrap() as usize,
+		    Some(c) if !c.is_ascii_whitespace() => {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid hex digit '{}'", c as char)));
+		    },
+		    _ => {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "transmission ends mid-packet"));
+		    }
+		};
+		self.peeked = None;
+		self.left = 4;
 	    }
-	    assert_eq!(l, total_len);
-	    return Some((packet, 22 + total_len, next));
-	} else {
-	    let total_packets = bin(&x[7..18]);
-	    //println!("packet len {}", total_packets);
-	    // length of subpackets
-	    let mut l: usize = 0;
-	    let mut next = &x[18..];
-	    let mut packet = Packet::op(version, id);
-	    for _ in 0..total_packets {
-		let (p, len, n) = parse_packet(next).unwrap();
-		packet.sub_packets.push(p);
-		next = n;
-		l += len;
+	    self.left -= 1;
+	    x = x * 2 + ((self.digit >> self.left) & 1);
+	    self.pos += 1;
+	}
+	Ok(x)
+    }
+
+    /// Discard the padding following a packet: the rest of the current digit,
+    /// and any zero digits that run up to whitespace or the end of the input.
+    /// Zero digits followed by anything else are the start of the next packet.
+    fn skip_padding(&mut self) -> io::Result<()> {
+	if self.digit & ((1 << self.left) - 1) != 0 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidData, "non-zero padding after packet"));
+	}
+	self.left = 0;
+	let mut zeros = 0;
+	while self.peek_byte()? == Some(b'0') {
+	    self.peeked = None;
+	    zeros += 1;
+	}
+	if self.peek_byte()?.is_some_and(|c| !c.is_ascii_whitespace()) {
+	    self.zeros = zeros;
+	}
+	Ok(())
+    }
+
+    /// Discard any whitespace, returning false if the input is exhausted.
+    fn skip_whitespace(&mut self) -> io::Result<bool> {
+	while let Some(c) = self.peek_byte()? {
+	    if !c.is_ascii_whitespace() {
+		return Ok(true);
+	    }
+	    self.peeked = None;
+	}
+	Ok(false)
+    }
+}
+
+fn read_packet<R: Read>(r: &mut BitReader<R>) -> io::Result<Packet> {
+    let version = r.bits(3)?;
+    let id = r.bits(3)?;
+    if id == 4 {
+	let mut lit: usize = 0;
+	loop {
+	    let more = r.bits(1)?;
+	    lit = lit.checked_mul(16)
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "literal too large"))?
+		+ r.bits(4)?;
+	    if more == 0 {
+		return Ok(Packet::literal(version, lit));
 	    }
+	}
+    }
 
-	    return Some((packet, 18+l, next));
+    let mut packet = Packet::op(version, id);
+    if r.bits(1)? == 0 {
+	// length of subpackets in bits
+	let total_len = r.bits(15)?;
+	let end = r.pos + total_len;
+	while r.pos < end {
+	    packet.sub_packets.push(read_packet(r)?);
+	}
+	if r.pos != end {
+	    return Err(io::Error::new(io::ErrorKind::InvalidData, "sub-packets overrun their length"));
+	}
+    } else {
+	let total_packets = r.bits(11)?;
+	for _ in 0..total_packets {
+	    packet.sub_packets.push(read_packet(r)?);
 	}
     }
+    Ok(packet)
+}
 
-    return None;
+/// Iterator over the top-level packets of a hex transmission.
+///
+/// Packets may follow each other directly or be separated by whitespace, and
+/// any zero padding after a packet is skipped. Each packet is yielded as soon
+/// as its last bit is read.
+pub struct PacketReader<R: Read> {
+    bits: BitReader<R>,
+    // whether a packet has been read, so padding may follow
+    after_packet: bool,
+    done: bool,
 }
 
-pub fn day16() {
-    let lines = read_lines("input/day16.txt", true).unwrap();
-    let packet = &lines[0];
-    //println!("{:04b}", 2);
+impl<R: Read> PacketReader<R> {
+    pub fn new(r: R) -> Self {
+	PacketReader { bits: BitReader::new(r), after_packet: false, done: false }
+    }
 
-    let mut  binary = vec![];
-    for p in packet.as_bytes() {
-	binary.extend(format!("{:04b}", hex(&[*p])).as_bytes());
+    fn read_next(&mut self) -> io::Result<Option<Packet>> {
+	if self.after_packet {
+	    self.bits.skip_padding()?;
+	}
+	if !self.bits.skip_whitespace()? {
+	    return Ok(None);
+	}
+	self.after_packet = true;
+	read_packet(&mut self.bits).map(Some)
     }
-    //println!("{}", String::from_utf8_lossy(&binary));
-    let (packet, _, _) = parse_packet(&binary).unwrap();
+}
+
+impl<R: Read> Iterator for PacketReader<R> {
+    type Item = io::Result<Packet>;
 
-    println!("{}", packet.sum_versions());
-    println!("{}", packet.interp());
+    fn next(&mut self) -> Option<Self::Item> {
+	if self.done {
+	    return None;
+	}
+	let r = self.read_next().transpose();
+	if !matches!(r, Some(Ok(_))) {
+	    self.done = true;
+	}
+	r
+    }
+}
 
+/// Decode a single packet from a hex transmission.
+pub fn decode_hex(packet: &str) -> io::Result<Packet> {
+    PacketReader::new(packet.as_bytes()).next()
+	.unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::UnexpectedEof, "empty transmission")))
+}
 
+pub fn day16() {
+    let f = File::open("input/day16.txt").unwrap();
+    for packet in PacketReader::new(f) {
+	let packet = packet.unwrap();
+	println!("{}", packet.sum_versions());
+	println!("{}", packet.interp());
+    }
 }
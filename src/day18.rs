@@ -1,199 +1,349 @@
 use crate::read_lines;
+use rayon::prelude::*;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::Add;
+use std::str::FromStr;
 
+/// A snailfish number: either a regular number, or a pair of snailfish numbers.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Token {
-    Left,
-    Right,
-    Num(usize)
+pub enum SnailfishNumber {
+    Regular(usize),
+    Pair(Box<SnailfishNumber>, Box<SnailfishNumber>)
 }
 
-impl Token {
-    fn num(&self) -> Option<usize> {
-	match self {
-	    Token::Num(x) => Some(*x),
-	    _ => None
+use SnailfishNumber::*;
+
+/// Which element of a pair to descend into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    L,
+    R
+}
+
+/// A single action taken while reducing a snailfish number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// The pair of regular numbers at `path` exploded.
+    Explode { path: Vec<Side>, pair: (usize, usize) },
+    /// The regular number `n` at `path` split.
+    Split { path: Vec<Side>, n: usize }
+}
+
+/// An action and the number that resulted from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    pub action: Action,
+    pub result: SnailfishNumber
+}
+
+// An explosion in progress: the exploded pair, and the halves that still
+// need to be added to the neighboring regular numbers.
+struct Exploded {
+    pair: (usize, usize),
+    left: Option<usize>,
+    right: Option<usize>
+}
+
+/// Parameters of snailfish reduction and magnitude.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnailfishRules {
+    /// Pairs nested inside this many pairs explode.
+    explode_depth: usize,
+    /// Regular numbers at least this large split. Must be at least 2.
+    split_threshold: usize,
+    /// Weights of the left and right elements of a pair in its magnitude.
+    magnitude_weights: (usize, usize)
+}
+
+impl SnailfishRules {
+    /// Fails if the split threshold is below 2, since splitting would never end.
+    pub fn new(explode_depth: usize, split_threshold: usize, magnitude_weights: (usize, usize)) -> Result<SnailfishRules, String> {
+	if split_threshold < 2 {
+	    return Err(format!("split threshold must be at least 2, not {}", split_threshold));
 	}
+	Ok(SnailfishRules { explode_depth, split_threshold, magnitude_weights })
     }
 }
 
-#[allow(dead_code)]
-fn dbg_tokens(x: &[Token]) {
-    for a in x {
-	match a {
-	    Token::Left => print!("["),
-	    Token::Right => print!("],"),
-	    Token::Num(a) => print!("{},", a)
-	}
+impl Default for SnailfishRules {
+    fn default() -> Self {
+	SnailfishRules { explode_depth: 4, split_threshold: 10, magnitude_weights: (3, 2) }
     }
-    //println!();
 }
 
-pub fn explode(x: &mut Vec<Token>) -> bool {
-    let mut depth = 0;
-    let mut last_num = None;
-    let mut found_num = None;
-    use Token::*;
-    for i in 0..x.len() {
-	match x[i] {
-	    Left => {
-		depth += 1;
-	    },
-	    Right => {
-		depth -= 1;
-	    },
-	    Num(_) if depth > 4 => {
-		if let Num(_) = x[i+1] {
-		    found_num = Some(i);
-		    break;
-		} else {
-		    last_num = Some(i);
-		}
-	    },
-	    Num(_) => {
-		last_num = Some(i);
+impl SnailfishNumber {
+    pub fn pair(a: SnailfishNumber, b: SnailfishNumber) -> SnailfishNumber {
+	Pair(Box::new(a), Box::new(b))
+    }
+
+    /// Explode the leftmost pair nested inside four pairs.
+    /// Return true iff a pair exploded.
+    pub fn explode(&mut self) -> bool {
+	self.explode_step(&SnailfishRules::default()).is_some()
+    }
+
+    fn explode_step(&mut self, rules: &SnailfishRules) -> Option<Action> {
+	let mut path = vec![];
+	self.explode_at(&mut path, rules.explode_depth).map(|e| Action::Explode { path, pair: e.pair })
+    }
+
+    // Explode the leftmost pair at least `depth` deep, leaving `path` pointing at it.
+    fn explode_at(&mut self, path: &mut Vec<Side>, depth: usize) -> Option<Exploded> {
+	let Pair(a, b) = self else {
+	    return None;
+	};
+	if path.len() >= depth {
+	    if let (Regular(x), Regular(y)) = (&**a, &**b) {
+		let e = Exploded { pair: (*x, *y), left: Some(*x), right: Some(*y) };
+		*self = Regular(0);
+		return Some(e);
 	    }
 	}
-    }
-    if let Some(f) = found_num {
-	// dbg_tokens(&x);
-	// add the left number to prev
-	if let Some(last) = last_num {
-	    x[last] = Token::Num(x[last].num().unwrap() + x[f].num().unwrap());
+	path.push(Side::L);
+	if let Some(mut e) = a.explode_at(path, depth) {
+	    if let Some(r) = e.right.take() {
+		b.add_leftmost(r);
+	    }
+	    return Some(e);
 	}
-	// add the right number to next, if possible.
-	for i in f+2..x.len() {
-	    if let Token::Num(a) = x[i] {
-		x[i] = Token::Num(a + x[f+1].num().unwrap());
-		break;
+	path.pop();
+	path.push(Side::R);
+	if let Some(mut e) = b.explode_at(path, depth) {
+	    if let Some(l) = e.left.take() {
+		a.add_rightmost(l);
 	    }
+	    return Some(e);
 	}
-	//dbg_tokens(&x);
-	// reduce
-	let (a, b) = x.split_at(f-1);
-	let (_, c) = b.split_at(4);
-	let mut r: Vec<Token> = vec![];
-	r.extend_from_slice(a);
-	r.push(Token::Num(0));
-	r.extend_from_slice(c);
+	path.pop();
+	None
+    }
 
-	*x = r;
-	//dbg_tokens(&x);
+    fn add_leftmost(&mut self, v: usize) {
+	match self {
+	    Regular(x) => *x += v,
+	    Pair(a, _) => a.add_leftmost(v)
+	}
+    }
 
-	return true;
+    fn add_rightmost(&mut self, v: usize) {
+	match self {
+	    Regular(x) => *x += v,
+	    Pair(_, b) => b.add_rightmost(v)
+	}
     }
 
-    false
-}
+    /// Split the leftmost regular number that is 10 or greater.
+    /// Return true iff a number was split.
+    pub fn split(&mut self) -> bool {
+	self.split_step(&SnailfishRules::default()).is_some()
+    }
+
+    fn split_step(&mut self, rules: &SnailfishRules) -> Option<Action> {
+	let mut path = vec![];
+	self.split_at(&mut path, rules.split_threshold).map(|n| Action::Split { n, path })
+    }
 
-pub fn split(x: &mut Vec<Token>) -> bool {
-    let mut split_index = None;
-    for i in 0..x.len() {
-	if let Token::Num(x) = x[i] {
-	    if x >= 10 {
-		split_index = Some(i);
-		break;
+    // Split the leftmost number at least `threshold`, leaving `path` pointing at it.
+    fn split_at(&mut self, path: &mut Vec<Side>, threshold: usize) -> Option<usize> {
+	match self {
+	    Regular(x) if *x >= threshold => {
+		let n = *x;
+		*self = Self::pair(Regular(n/2), Regular(n.div_ceil(2)));
+		Some(n)
+	    },
+	    Regular(_) => None,
+	    Pair(a, b) => {
+		for (side, c) in [(Side::L, a), (Side::R, b)] {
+		    path.push(side);
+		    if let Some(n) = c.split_at(path, threshold) {
+			return Some(n);
+		    }
+		    path.pop();
+		}
+		None
 	    }
 	}
     }
 
-    if let Some(i) = split_index {
-	let (a, b) = x.split_at(i);
-	let (_, c) = b.split_at(1);
-	let n = x[i].num().unwrap();
-	let mut r = vec![];
-	r.extend_from_slice(a);
-	r.extend_from_slice(&[Token::Left, Token::Num(n/2), Token::Num((n+1)/2), Token::Right]);
-	r.extend_from_slice(c);
+    /// Perform a single reduction action, if any applies.
+    fn step(&mut self, rules: &SnailfishRules) -> Option<Action> {
+	self.explode_step(rules).or_else(|| self.split_step(rules))
+    }
 
-	*x = r;
-	return true;
+    pub fn reduce(&mut self) {
+	self.reduce_with(&SnailfishRules::default());
     }
-    false
-}
 
-pub fn reduce(p: &mut Vec<Token>) {
-    loop {
-	if explode(p) {
-	    assert_eq!(p.iter().filter(|x| **x == Token::Left).count(),
-		       p.iter().filter(|x| **x == Token::Right).count());
-	    continue;
+    pub fn reduce_with(&mut self, rules: &SnailfishRules) {
+	while self.step(rules).is_some() {
 	}
-	if split(p) {
-	assert_eq!(p.iter().filter(|x| **x == Token::Left).count(),
-		   p.iter().filter(|x| **x == Token::Right).count());
-	    continue;
+    }
+
+    /// Reduce, recording every action along with the number it produced.
+    pub fn reduce_traced(&mut self) -> Vec<TraceStep> {
+	self.reduce_traced_with(&SnailfishRules::default())
+    }
+
+    pub fn reduce_traced_with(&mut self, rules: &SnailfishRules) -> Vec<TraceStep> {
+	let mut trace = vec![];
+	while let Some(action) = self.step(rules) {
+	    trace.push(TraceStep { action, result: self.clone() });
 	}
+	trace
+    }
 
-	break;
+    /// Add two numbers under the given rules.
+    pub fn add_with(self, rhs: SnailfishNumber, rules: &SnailfishRules) -> SnailfishNumber {
+	let mut r = Self::pair(self, rhs);
+	r.reduce_with(rules);
+	r
+    }
+
+    /// Add two numbers, returning the sum and the trace of its reduction.
+    pub fn add_traced(self, rhs: SnailfishNumber) -> (SnailfishNumber, Vec<TraceStep>) {
+	self.add_traced_with(rhs, &SnailfishRules::default())
+    }
+
+    pub fn add_traced_with(self, rhs: SnailfishNumber, rules: &SnailfishRules) -> (SnailfishNumber, Vec<TraceStep>) {
+	let mut r = Self::pair(self, rhs);
+	let trace = r.reduce_traced_with(rules);
+	(r, trace)
+    }
+
+    /// Add the numbers in order, or return None if there are none.
+    pub fn sum_all<I: IntoIterator<Item = SnailfishNumber>>(nums: I) -> Option<SnailfishNumber> {
+	nums.into_iter().reduce(Add::add)
+    }
+
+    pub fn magnitude(&self) -> usize {
+	self.magnitude_with(&SnailfishRules::default())
     }
-}
 
-pub fn parse(p: &str) -> Vec<Token> {
-    p.bytes().filter_map(|b| {
-	match b {
-	    b'[' => Some(Token::Left),
-	    b']' => Some(Token::Right),
-	    b',' => None,
-	    b'0'..=b'9' => Some(Token::Num((b - b'0') as usize)),
-	    _ => { unreachable!("cannot parse {}", b as char); }
+    pub fn magnitude_with(&self, rules: &SnailfishRules) -> usize {
+	let (wl, wr) = rules.magnitude_weights;
+	match self {
+	    Regular(x) => *x,
+	    Pair(a, b) => wl * a.magnitude_with(rules) + wr * b.magnitude_with(rules)
 	}
-    }).collect()
+    }
 }
 
-pub fn add(x: Vec<Token>, y: Vec<Token>) -> Vec<Token> {
-    let mut v = vec![Token::Left];
-    v.extend(x);
-    v.extend(y);
-    v.push(Token::Right);
-    reduce(&mut v);
-    assert_eq!(v.iter().filter(|x| **x == Token::Left).count(),
-	       v.iter().filter(|x| **x == Token::Right).count());
-    v
+impl Add for SnailfishNumber {
+    type Output = SnailfishNumber;
+
+    fn add(self, rhs: SnailfishNumber) -> SnailfishNumber {
+	self.add_with(rhs, &SnailfishRules::default())
+    }
 }
 
-pub fn magnitude(x: &[Token]) -> usize {
-    let mut mag_stack = vec![];
+/// Add the numbers in order.
+///
+/// # Panics
+///
+/// Snailfish numbers have no zero, so summing no numbers panics.
+/// Use [`SnailfishNumber::sum_all`] when the list may be empty.
+impl Sum for SnailfishNumber {
+    fn sum<I: Iterator<Item = SnailfishNumber>>(iter: I) -> SnailfishNumber {
+	Self::sum_all(iter).expect("cannot sum an empty list of snailfish numbers")
+    }
+}
 
-    for a in x {
-	match a {
-	    Token::Num(n) => {
-		mag_stack.push(*n);
-	    },
-	    Token::Right => {
-		let right = mag_stack.pop().unwrap();
-		let left = mag_stack.pop().unwrap();
-		mag_stack.push(3*left + 2 * right);
+impl fmt::Display for SnailfishNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Regular(x) => write!(f, "{}", x),
+	    Pair(a, b) => write!(f, "[{},{}]", a, b)
+	}
+    }
+}
+
+/// Write a path as e.g. `L/R/L/L`, or `root` if empty.
+fn fmt_path(path: &[Side], f: &mut fmt::Formatter) -> fmt::Result {
+    if path.is_empty() {
+	return write!(f, "root");
+    }
+    let p: Vec<&str> = path.iter().map(|s| match s { Side::L => "L", Side::R => "R" }).collect();
+    write!(f, "{}", p.join("/"))
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Action::Explode { path, pair } => {
+		write!(f, "explode at ")?;
+		fmt_path(path, f)?;
+		write!(f, " with pair [{},{}]", pair.0, pair.1)
 	    },
-	    _ => {
+	    Action::Split { path, n } => {
+		write!(f, "split {} at ", n)?;
+		fmt_path(path, f)
 	    }
 	}
     }
-    assert_eq!(mag_stack.len(), 1);
-    mag_stack[0]
 }
 
-pub fn day18() {
-    let lines = read_lines("input/day18.txt", true).unwrap();
-
-    let a = parse(&lines[0]);
-    let r = lines[1..].iter().map(|x| parse(&x)).fold(a, add);
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f, "{}: {}", self.action, self.result)
+    }
+}
 
-    // let a = parse("[[[[4,3],4],4],[7,[[8,4],9]]]");
-    // let b = parse("[1,1]");
-    // let c = add(a, b);
-    // dbg_tokens(&c);
+// Parse a single snailfish number from the front of `s`, returning the rest.
+fn parse_number(s: &[u8]) -> Result<(SnailfishNumber, &[u8]), String> {
+    let expect = |s: &[u8], c: u8| match s.first() {
+	Some(x) if *x == c => Ok(()),
+	Some(x) => Err(format!("expected '{}', found '{}'", c as char, *x as char)),
+	None => Err(format!("expected '{}', found end of input", c as char))
+    };
+    match s.first() {
+	Some(b'[') => {
+	    let (a, rest) = parse_number(&s[1..])?;
+	    expect(rest, b',')?;
+	    let (b, rest) = parse_number(&rest[1..])?;
+	    expect(rest, b']')?;
+	    Ok((SnailfishNumber::pair(a, b), &rest[1..]))
+	},
+	Some(b'0'..=b'9') => {
+	    let n = s.iter().take_while(|x| x.is_ascii_digit()).count();
+	    let x = String::from_utf8_lossy(&s[..n]).parse().map_err(|e| format!("bad number: {}", e))?;
+	    Ok((Regular(x), &s[n..]))
+	},
+	Some(x) => Err(format!("unexpected '{}'", *x as char)),
+	None => Err("unexpected end of input".to_string())
+    }
+}
 
-    println!("{}", magnitude(&r));
+impl FromStr for SnailfishNumber {
+    type Err = String;
 
-    let sf: Vec<_> = lines.iter().map(|x| parse(&x)).collect();
-    let mut max = 0;
-    for i in 0..sf.len() {
-	for j in 0..sf.len() {
-	    if i == j {
-		continue;
-	    }
-	    max = std::cmp::max(max, magnitude(&add(sf[i].clone(), sf[j].clone())));
+    fn from_str(s: &str) -> Result<SnailfishNumber, String> {
+	let (n, rest) = parse_number(s.trim().as_bytes())?;
+	if !rest.is_empty() {
+	    return Err(format!("trailing input '{}'", String::from_utf8_lossy(rest)));
 	}
+	Ok(n)
     }
+}
+
+/// Find the largest magnitude of a sum of two different numbers.
+/// Return it along with the pair `(i, j)` that produced it, as `nums[i] + nums[j]`.
+pub fn max_pair_magnitude(nums: &[SnailfishNumber], rules: &SnailfishRules) -> Option<(usize, (usize, usize))> {
+    (0..nums.len()).into_par_iter()
+	.flat_map(|i| (0..nums.len()).into_par_iter().filter(move |j| *j != i).map(move |j| (i, j)))
+	.map(|(i, j)| (nums[i].clone().add_with(nums[j].clone(), rules).magnitude_with(rules), (i, j)))
+	// prefer the first pair on ties
+	.max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+}
+
+pub fn day18() {
+    let lines = read_lines("input/day18.txt", true).unwrap();
+
+    let sf: Vec<SnailfishNumber> = lines.iter().map(|x| x.parse().unwrap()).collect();
+    let r = SnailfishNumber::sum_all(sf.iter().cloned()).expect("no snailfish numbers in input");
+
+    println!("{}", r.magnitude());
+
+    let (max, _) = max_pair_magnitude(&sf, &SnailfishRules::default()).unwrap();
     println!("{}", max);
 }
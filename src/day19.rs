@@ -1,63 +1,128 @@
 use crate::read_lines;
-use std::collections::{HashMap, HashSet};
-use integer_sqrt::IntegerSquareRoot;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{self, Write};
 use nalgebra as na;
 use once_cell::sync::Lazy;
+use rayon::prelude::*;
 
-type V = na::Vector3<isize>;
-type A = na::Point3<isize>;
+type V<const D: usize = 3> = na::SVector<isize, D>;
+type A<const D: usize = 3> = na::Point<isize, D>;
 
-type R = na::Matrix3<isize>;
+type R<const D: usize = 3> = na::SMatrix<isize, D, D>;
 
+/// Number of beacons two scanners must have in common to be aligned.
+const MIN_BEACONS: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlignError {
+    /// No orientation and offset brings enough points into agreement.
+    NoMatch,
+    /// The scanner at this index could not be aligned with any other.
+    Unreachable(usize),
+}
+
+impl std::fmt::Display for AlignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+	match self {
+	    AlignError::NoMatch => write!(f, "no alignment with enough points in common"),
+	    AlignError::Unreachable(i) => write!(f, "scanner {} could not be aligned with the others", i),
+	}
+    }
+}
+
+impl std::error::Error for AlignError {}
+
+/// Position and orientation of a scanner, relative to scanner 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannerPose<const D: usize = 3> {
+    pub origin: A<D>,
+    /// Rotation from the scanner's own frame into scanner 0's frame.
+    pub rotation: R<D>,
+}
+
+impl<const D: usize> ScannerPose<D> {
+    /// Transform a point seen by this scanner into scanner 0's frame.
+    pub fn transform(&self, v: &V<D>) -> V<D> {
+	(self.origin + self.rotation * v).coords
+    }
+}
+
+/// The points seen by one scanner. The alignment machinery works in any
+/// dimension `D`; the puzzle itself is in 3.
 #[derive(Clone)]
-struct Scanner {
-    beacons: Vec<V>,
-    origin: A,
-    dist_map: HashMap<V, Vec<(usize, usize)>>,
+pub struct Scanner<const D: usize = 3> {
+    beacons: Vec<V<D>>,
+    origin: A<D>,
+    dist_map: HashMap<V<D>, Vec<(usize, usize)>>,
 }
 
-fn sort_dist(x: &V, y: &V) -> V {
+fn sort_dist<const D: usize>(x: &V<D>, y: &V<D>) -> V<D> {
     let mut r: Vec<_> = x.iter().zip(y.iter()).map(|(a, b)| (a - b).abs()).collect();
     r.sort();
-    V::new(r[0], r[1], r[2])
+    V::from_vec(r)
 }
 
-static ORIENTS: Lazy<Vec<na::Matrix3<isize>>> = Lazy::new(|| {
-    let mut orients = vec![];
-    for x in -3..=3isize {
-	for y in -3..=3isize {
-	    for z in -3..=3isize {
-		if x == 0 || y == 0 || z == 0 {
-		    continue;
-		}
-		let xa = x.abs() as usize;
-		let ya = y.abs() as usize;
-		let za = z.abs() as usize;
+/// All permutations of `0..n`, paired with their parity (1 for even, -1 for odd).
+fn permutations(n: usize) -> Vec<(Vec<usize>, isize)> {
+    if n == 0 {
+	return vec![(vec![], 1)];
+    }
+    let mut perms = vec![];
+    for (p, parity) in permutations(n - 1) {
+	// insert n-1 at each position, shifting past k elements
+	for k in 0..n {
+	    let mut q = p.clone();
+	    q.insert(n - 1 - k, n - 1);
+	    perms.push((q, if k % 2 == 0 { parity } else { -parity }));
+	}
+    }
+    perms
+}
 
-		if xa == ya || ya == za || za == xa {
-		    continue;
-		}
-		let mut m = na::Matrix3::zeros();
-		m[(0, (xa-1) as usize)] = x.signum();
-		m[(1, (ya-1) as usize)] = y.signum();
-		m[(2, (za-1) as usize)] = z.signum();
+/// The signed permutation matrices in `D` dimensions (the hyperoctahedral
+/// group). With `proper_only`, keep only the rotations, those with
+/// determinant 1.
+pub fn orientations<const D: usize>(proper_only: bool) -> Vec<R<D>> {
+    let mut orients = vec![];
+    for (perm, parity) in permutations(D) {
+	for signs in 0..1usize << D {
+	    let mut m = R::<D>::zeros();
+	    let mut det = parity;
+	    for (i, p) in perm.iter().enumerate() {
+		let sign = if signs >> i & 1 == 1 { -1 } else { 1 };
+		m[(i, *p)] = sign;
+		det *= sign;
+	    }
+	    if det == 1 || !proper_only {
 		orients.push(m);
 	    }
 	}
     }
     orients
-});
+}
+
+/// The 24 rotations of 3D space that keep the axes aligned.
+pub static ORIENTS: Lazy<Vec<R>> = Lazy::new(|| orientations(true));
+
+impl<const D: usize> Scanner<D> {
+    /// A scanner that sees the given points, in its own frame.
+    pub fn from_points(points: &[V<D>]) -> Self {
+	let mut s = Self::new();
+	for p in points {
+	    s.add_beacon(p);
+	}
+	s
+    }
 
-impl Scanner {
-    fn new() -> Scanner {
+    fn new() -> Self {
 	Scanner {
 	    beacons: vec![],
 	    dist_map: HashMap::new(),
-	    origin: A::new(0, 0, 0),
+	    origin: A::origin(),
 	}
     }
 
-    fn add_beacon(&mut self, beacon: &V) {
+    fn add_beacon(&mut self, beacon: &V<D>) {
 	let n = self.beacons.len();
 	for (i, b) in self.beacons.iter().enumerate() {
 	    let sd = sort_dist(b, beacon);
@@ -66,16 +131,18 @@ impl Scanner {
 	self.beacons.push(*beacon);
     }
 
+    /// Number of beacon pairs whose distance signature both scanners share,
+    /// counting repeated signatures as many times as both can match them.
     fn len_intersect(&self, other: &Self) -> usize {
-	let a: HashSet<V> = self.dist_map.keys().cloned().collect();
-	let b: HashSet<V> = other.dist_map.keys().cloned().collect();
-
-	a.intersection(&b).count()
+	let (a, b) = if self.dist_map.len() <= other.dist_map.len() { (self, other) } else { (other, self) };
+	a.dist_map.iter()
+	    .filter_map(|(k, v)| b.dist_map.get(k).map(|w| std::cmp::min(v.len(), w.len())))
+	    .sum()
     }
 
-    fn reorient(&self, v: V, r: &R) -> Self {
+    fn reorient(&self, v: V<D>, r: &R<D>) -> Self {
 	//let a = inverse_orient(&a);
-	let b: Vec<V> = self
+	let b: Vec<V<D>> = self
 	    .beacons
 	    .iter()
 	    .map(|b| {
@@ -90,77 +157,54 @@ impl Scanner {
 	}
     }
 
-    // relative position, re-orient
-    fn align(&self, other: &Self) -> Self {
-	assert!(self.len_intersect(other) >= 66);
-
-	let mut b2b: HashMap<usize, HashSet<usize>> = HashMap::new();
-
-	// for _ in 0..self.beacons.len()  {
-	//     b2b.push((0..other.beacons.len()).collect());
-	// }
-
+    /// Find the pose of `other` in the frame of this (already aligned) scanner,
+    /// using orientations from `group` and requiring `min_common` points in common.
+    fn align(&self, other: &Self, group: &[R<D>], min_common: usize) -> Result<ScannerPose<D>, AlignError> {
+	// beacons at either end of a shared distance signature may correspond
+	let mut candidates = vec![];
 	for (k, v) in self.dist_map.iter() {
-	    if other.dist_map.contains_key(k) {
-		// each in the pair can only map to the other pair.
-		assert!(v.len() == 1 || other.dist_map[k].len() == 1);
-		let r = v[0];
-		let v2 = other.dist_map[k][0];
-		let s: HashSet<usize> = [v2.0, v2.1].iter().cloned().collect();
-		let n = b2b
-		    .entry(r.0)
-		    .or_insert_with(|| s.clone())
-		    .intersection(&s)
-		    .cloned()
-		    .collect();
-		b2b.insert(r.0, n);
-		let n = b2b
-		    .entry(r.1)
-		    .or_insert_with(|| s.clone())
-		    .intersection(&s)
-		    .cloned()
-		    .collect();
-		b2b.insert(r.1, n);
+	    if let Some(v2) = other.dist_map.get(k) {
+		for (a, b) in v {
+		    for (c, d) in v2 {
+			candidates.extend([(*a, *c), (*a, *d), (*b, *c), (*b, *d)]);
+		    }
+		}
 	    }
 	}
-	//println!("{}", b2b.len());
-
-	// 1-1 mapping across common beacons
-	for ori in &*ORIENTS {
-	    // make sure the first two beacons map to each other.
-	    let bs: Vec<(usize, usize)> = b2b
-		.iter()
-		.take(4)
-		.map(|(a, b)| (*a, *b.iter().next().unwrap()))
-		.collect();
-
-	    let o1 = {
-		let b1 = self.beacons[bs[0].0];
-		let b2 = other.beacons[bs[0].1];
-		b1 - ori * b2
-	    };
-
-	    let o2 = {
-		let b1 = self.beacons[bs[1].0];
-		let b2 = other.beacons[bs[1].1];
-		b1 - ori * b2
-	    };
-	    let o3 = {
-		let b1 = self.beacons[bs[2].0];
-		let b2 = other.beacons[bs[2].1];
-		b1 - ori * b2
-	    };
-	    let o4 = {
-		let b1 = self.beacons[bs[3].0];
-		let b2 = other.beacons[bs[3].1];
-		b1 - ori * b2
-	    };
-	    if o1 == o2 && o2 == o3 && o3 == o4 {
-		return other.reorient(o1, ori);
-	    }
+	// each common point is in min_common - 1 common pairs, so the true offset
+	// is implied at least min_common * (min_common - 1) times.
+	if let Some(pose) = self.best_pose(other, &candidates, group, min_common, min_common * min_common.saturating_sub(1)) {
+	    return Ok(pose);
 	}
 
-	panic!();
+	// fall back to trying every pair of beacons
+	let all: Vec<_> = (0..self.beacons.len())
+	    .flat_map(|i| (0..other.beacons.len()).map(move |j| (i, j)))
+	    .collect();
+	self.best_pose(other, &all, group, min_common, min_common).ok_or(AlignError::NoMatch)
+    }
+
+    /// Under every orientation, count how many candidate correspondences imply
+    /// each offset. Check the offsets implied at least `min_votes` times, and
+    /// return the pose that brings the most points into agreement, if it has
+    /// at least `min_common`.
+    fn best_pose(&self, other: &Self, candidates: &[(usize, usize)], group: &[R<D>],
+		 min_common: usize, min_votes: usize) -> Option<ScannerPose<D>> {
+	let own: HashSet<V<D>> = self.beacons.iter().cloned().collect();
+	let mut best: Option<(usize, ScannerPose<D>)> = None;
+	for ori in group {
+	    let mut votes: HashMap<V<D>, usize> = HashMap::new();
+	    for (i, j) in candidates {
+		*votes.entry(self.beacons[*i] - ori * other.beacons[*j]).or_default() += 1;
+	    }
+	    for (o, _) in votes.into_iter().filter(|(_, n)| *n >= min_votes) {
+		let inliers = other.beacons.iter().filter(|b| own.contains(&(ori * *b + o))).count();
+		if inliers >= min_common && best.as_ref().is_none_or(|(n, _)| inliers > *n) {
+		    best = Some((inliers, ScannerPose { origin: A::from(o), rotation: *ori }));
+		}
+	    }
+	}
+	best.map(|(_, pose)| pose)
     }
 }
 
@@ -174,70 +218,197 @@ fn orient_location(v: &V, r: &R) -> V {
     r * v
 }
 
-pub fn day19() {
-    let lines = read_lines("input/day19.txt", false).unwrap();
-    let mut curr = Scanner::new();
+/// Parse the scanner reports, one scanner per `--- scanner N ---` header.
+pub fn parse_scanners<const D: usize>(lines: &[String]) -> Vec<Scanner<D>> {
+    let mut curr: Option<Scanner<D>> = None;
     let mut scanners = vec![];
-    for line in lines {
+    for line in lines.iter().map(|x| x.trim()) {
 	if line.is_empty() {
-	    scanners.push(curr);
-	    curr = Scanner::new()
 	} else if line.contains("scanner") {
+	    scanners.extend(curr.replace(Scanner::new()));
 	} else {
 	    let toks: Vec<_> = line.split(",").map(|x| x.parse::<isize>().unwrap()).collect();
-	    let v = V::new(toks[0], toks[1], toks[2]);
-	    curr.add_beacon(&v);
+	    assert_eq!(toks.len(), D, "expected {} coordinates in '{}'", D, line);
+	    let v = V::from_vec(toks);
+	    curr.get_or_insert_with(Scanner::new).add_beacon(&v);
 	}
     }
-    //scanners.push(curr);
-
-    let mut total_beacons: usize = scanners.iter().map(|x| x.beacons.len()).sum();
+    scanners.extend(curr);
+    scanners
+}
 
-    let min_beacons = 12;
-    let min_rel = min_beacons * (min_beacons - 1) / 2;
-    let mut orients: Vec<_> = vec![];
-    for i in 0..scanners.len() {
-	for j in i + 1..scanners.len() {
-	    let li = scanners[i].len_intersect(&scanners[j]);
-	    if li >= min_rel {
-		total_beacons -= (li * 2).integer_sqrt() + 1;
-		orients.push((i, j)); //, scanners[i].align(&scanners[j])));
-		orients.push((j, i)); //, scanners[j].align(&scanners[i])));
+/// Find the pose of every scanner relative to scanner 0, using orientations
+/// from `group` and requiring overlapping scanners to have `min_common` points
+/// in common.
+pub fn align_all<const D: usize>(scanners: &[Scanner<D>], group: &[R<D>], min_common: usize)
+				 -> Result<Vec<ScannerPose<D>>, AlignError> {
+    let n = scanners.len();
+    let min_rel = min_common * min_common.saturating_sub(1) / 2;
 
-		// orients.push((i, j, scanners[i].align(&scanners[j])));
-		// orients.push((j, i, scanners[j].align(&scanners[i])));
-	    }
-	}
+    // overlap graph, weighted by the number of shared distance signatures
+    let edges: Vec<(usize, usize, usize)> = (0..n).into_par_iter()
+	.flat_map(|i| (i + 1..n).into_par_iter().map(move |j| (i, j)))
+	.map(|(i, j)| (scanners[i].len_intersect(&scanners[j]), i, j))
+	.filter(|(w, _, _)| *w >= min_rel)
+	.collect();
+    let mut adj: Vec<Vec<(usize, usize)>> = vec![vec![]; n];
+    for (w, i, j) in edges {
+	adj[i].push((w, j));
+	adj[j].push((w, i));
     }
-    println!("{}", total_beacons);
 
-    // find the positions and orient of all relative to 0
-    let mut found: Vec<Option<Scanner>> = vec![None; scanners.len()];
+    // grow a maximum spanning tree from scanner 0, so each scanner is
+    // aligned along its strongest link to one already found.
+    let mut found: Vec<Option<Scanner<D>>> = vec![None; n];
+    let mut poses: Vec<Option<ScannerPose<D>>> = vec![None; n];
     found[0] = Some(scanners[0].clone());
-    let mut active = vec![0];
-
-    while let Some(a) = active.pop() {
-	// println!("{}", a);
-	// go through every alignment of a with something.
-	for (i, j) in &orients {
-	    if *i == a && found[*j].is_none() {
-		found[*j] = Some(found[*i].as_ref().unwrap().align(&scanners[*j]));
-		active.push(*j);
-	    }
+    poses[0] = Some(ScannerPose { origin: A::origin(), rotation: R::identity() });
+    let mut links: BinaryHeap<(usize, usize, usize)> = adj[0].iter().map(|(w, j)| (*w, 0, *j)).collect();
+
+    while let Some((_, i, j)) = links.pop() {
+	if found[j].is_some() {
+	    continue;
 	}
+	// a weaker link may still succeed
+	let Ok(pose) = found[i].as_ref().unwrap().align(&scanners[j], group, min_common) else {
+	    continue;
+	};
+	found[j] = Some(scanners[j].reorient(pose.origin.coords, &pose.rotation));
+	poses[j] = Some(pose);
+	links.extend(adj[j].iter().filter(|(_, k)| found[*k].is_none()).map(|(w, k)| (*w, j, *k)));
     }
 
-    for x in 0..scanners.len() {
-	if found[x].is_none() {
-	    println!("bad: {}", x);
+    poses.into_iter().enumerate().map(|(i, p)| p.ok_or(AlignError::Unreachable(i))).collect()
+}
+
+/// Every distinct beacon, in scanner 0's frame.
+pub fn beacon_map<const D: usize>(scanners: &[Scanner<D>], poses: &[ScannerPose<D>]) -> Vec<V<D>> {
+    let beacons: HashSet<V<D>> = scanners.iter().zip(poses)
+	.flat_map(|(s, p)| s.beacons.iter().map(|b| p.transform(b)))
+	.collect();
+    let mut beacons: Vec<V<D>> = beacons.into_iter().collect();
+    beacons.sort_by(|a, b| a.as_slice().cmp(b.as_slice()));
+    beacons
+}
+
+/// Text formats for exporting the beacon map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloudFormat {
+    Ply,
+    Obj,
+}
+
+impl std::str::FromStr for CloudFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CloudFormat, String> {
+	match s {
+	    "ply" => Ok(CloudFormat::Ply),
+	    "obj" => Ok(CloudFormat::Obj),
+	    _ => Err(format!("unknown point cloud format '{}', expected 'ply' or 'obj'", s))
 	}
     }
+}
+
+const BEACON_COLOR: [u8; 3] = [64, 160, 255];
+const SCANNER_COLOR: [u8; 3] = [255, 64, 0];
+
+/// Write the beacons and scanner origins as an ASCII point cloud, with the
+/// scanners colored differently from the beacons.
+pub fn write_point_cloud<W: Write>(w: &mut W, format: CloudFormat, beacons: &[V], poses: &[ScannerPose]) -> io::Result<()> {
+    let points: Vec<(V, [u8; 3])> = beacons.iter().map(|b| (*b, BEACON_COLOR))
+	.chain(poses.iter().map(|p| (p.origin.coords, SCANNER_COLOR)))
+	.collect();
+    match format {
+	CloudFormat::Ply => {
+	    writeln!(w, "ply")?;
+	    writeln!(w, "format ascii 1.0")?;
+	    writeln!(w, "comment {} beacons, {} scanners", beacons.len(), poses.len())?;
+	    writeln!(w, "element vertex {}", points.len())?;
+	    for p in ["x", "y", "z"] {
+		writeln!(w, "property int {}", p)?;
+	    }
+	    for p in ["red", "green", "blue"] {
+		writeln!(w, "property uchar {}", p)?;
+	    }
+	    writeln!(w, "end_header")?;
+	    for (v, c) in &points {
+		writeln!(w, "{} {} {} {} {} {}", v[0], v[1], v[2], c[0], c[1], c[2])?;
+	    }
+	},
+	CloudFormat::Obj => {
+	    // vertex colors are a common extension, as floats in 0..1
+	    writeln!(w, "# {} beacons, {} scanners", beacons.len(), poses.len())?;
+	    for (v, c) in &points {
+		let [r, g, b] = c.map(|x| x as f64 / 255.0);
+		writeln!(w, "v {} {} {} {:.3} {:.3} {:.3}", v[0], v[1], v[2], r, g, b)?;
+	    }
+	    // point elements, so viewers show the vertices
+	    writeln!(w, "g beacons")?;
+	    for i in 0..beacons.len() {
+		writeln!(w, "p {}", i + 1)?;
+	    }
+	    writeln!(w, "g scanners")?;
+	    for i in beacons.len()..points.len() {
+		writeln!(w, "p {}", i + 1)?;
+	    }
+	},
+    }
+    Ok(())
+}
+
+/// Align the puzzle input and write its beacon map to `path`.
+pub fn export_point_cloud(format: CloudFormat, path: &str) -> io::Result<()> {
+    let lines = read_lines("input/day19.txt", false)?;
+    let scanners: Vec<Scanner> = parse_scanners(&lines);
+    let poses = align_all(&scanners, &ORIENTS, MIN_BEACONS)
+	.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut w = io::BufWriter::new(std::fs::File::create(path)?);
+    write_point_cloud(&mut w, format, &beacon_map(&scanners, &poses), &poses)?;
+    w.flush()
+}
+
+pub fn day19() {
+    let lines = read_lines("input/day19.txt", false).unwrap();
+    let scanners: Vec<Scanner> = parse_scanners(&lines);
+
+    let poses = align_all(&scanners, &ORIENTS, MIN_BEACONS).unwrap();
+    println!("{}", beacon_map(&scanners, &poses).len());
+
     let mut max_dist = 0;
-    for i in 0..found.len() {
-	for j in i+1..found.len() {
-	    let d = found[i].as_ref().unwrap().origin - found[j].as_ref().unwrap().origin;
+    for i in 0..poses.len() {
+	for j in i+1..poses.len() {
+	    let d = poses[i].origin - poses[j].origin;
 	    max_dist = std::cmp::max(max_dist, d[0].abs() + d[1].abs() + d[2].abs());
 	}
     }
     println!("{}", max_dist);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_with_repeated_signatures() {
+	// a lattice repeats every distance signature many times, and a few
+	// asymmetric points pin down which of its symmetries is the right one
+	let lattice = (0..27).map(|i| V::from([i % 3 * 5, i / 3 % 3 * 5, i / 9 * 5]));
+	let asym = (1..6).map(|i| V::from([i * 7, i * i - 20, i * i * i % 11]));
+	let shared: Vec<V> = lattice.chain(asym).collect();
+	let only0 = [V::from([300, 0, 0]), V::from([-300, 40, 1])];
+	let only1 = [V::from([0, 0, -400]), V::from([17, 500, 3]), V::from([-9, -9, 600])];
+
+	let pose = ScannerPose { origin: A::from([100, -50, 25]), rotation: ORIENTS[7] };
+	// scanner 1 sees the world through the inverse of its pose
+	let seen1: Vec<V> = shared.iter().chain(&only1)
+	    .map(|p| pose.rotation.transpose() * (p - pose.origin.coords))
+	    .collect();
+	let seen0: Vec<V> = shared.iter().chain(&only0).cloned().collect();
+	let scanners = [Scanner::from_points(&seen0), Scanner::from_points(&seen1)];
+
+	let poses = align_all(&scanners, &ORIENTS, MIN_BEACONS).unwrap();
+	assert_eq!(poses[1], pose);
+	assert_eq!(beacon_map(&scanners, &poses).len(), shared.len() + only0.len() + only1.len());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::read_lines;
+use ndarray as nd;
+use nd::prelude::*;
+use rayon::prelude::*;
+use std::fmt;
+
+fn to_num(x: &u8) -> u8 {
+    match *x {
+	b'.' => 0,
+	b'#' => 1,
+	_ => panic!()
+    }
+}
+
+pub fn enhance(arr: &Array2<u8>, m: &[u8], def: u8) -> Array2<u8> {
+    let d = arr.dim();
+    let nd = (d.0 + 2, d.1 + 2);
+    
+    let mut new_arr = Array2::zeros(nd);
+
+    for i in -1..=d.0 as isize {
+	for j in -1..=d.1 as isize {
+	    let mut s: usize = 0;
+	    for a in -1..=1 {
+		for b in -1..=1 {
+		    let y = i + a;
+		    let x = j + b;
+		    let v = if y < 0 || y >= d.0 as isize {
+			def
+		    } else if x < 0 || x >= d.1 as isize {
+			def
+		    }  else {
+			arr[[y as usize, x as usize]]
+		    };
+		    s = s * 2 + v as usize;
+		}
+	    }
+	    
+	    new_arr[[(i+1) as usize, (j+1) as usize]] = m[s];
+	}
+    }
+
+    new_arr
+}
+
+/// An image on an infinite plane: a finite window of pixels, with every
+/// pixel outside the window equal to `background`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InfiniteImage {
+    pub pixels: Array2<u8>,
+    pub background: u8,
+}
+
+impl InfiniteImage {
+    /// An image on a dark background.
+    pub fn new(pixels: Array2<u8>) -> InfiniteImage {
+	InfiniteImage { pixels, background: 0 }
+    }
+
+    /// Apply the 512-entry enhancement algorithm `m`.
+    pub fn enhance(&self, m: &[u8]) -> InfiniteImage {
+	assert_eq!(m.len(), 512, "enhancement algorithm must have 512 entries");
+	InfiniteImage {
+	    pixels: enhance(&self.pixels, m, self.background),
+	    // the background is an all-dark or all-light neighborhood
+	    background: if self.background == 0 { m[0] } else { m[511] },
+	}
+    }
+
+    /// Apply the enhancement algorithm `m` `n` times, with the same result as
+    /// calling `enhance` `n` times.
+    ///
+    /// Two buffers sized for the final image are reused for every step, each
+    /// cell's index is rolled over from its left neighbor's, and rows are
+    /// computed in parallel.
+    pub fn enhance_n(&self, m: &[u8], n: usize) -> InfiniteImage {
+	assert_eq!(m.len(), 512, "enhancement algorithm must have 512 entries");
+	let (h, w) = self.pixels.dim();
+	// room for the final image, plus two cells of background on each side
+	let stride = w + 2 * n + 4;
+	let mut src = vec![self.background; (h + 2 * n + 4) * stride];
+	let mut dst = src.clone();
+	for ((i, j), v) in self.pixels.indexed_iter() {
+	    src[(i + n + 2) * stride + j + n + 2] = *v;
+	}
+
+	let mut bg = self.background;
+	for k in 0..n {
+	    // src holds the image after k steps, with its top-left corner at
+	    // (top, top). Everything past it must read as background.
+	    let top = n + 2 - k;
+	    let (sh, sw) = (h + 2 * k, w + 2 * k);
+	    fill_ring(&mut src, stride, top, sh, sw, bg);
+
+	    let (dtop, dw) = (top - 1, sw + 2);
+	    let prev = &src;
+	    dst[dtop * stride..(dtop + sh + 2) * stride].par_chunks_mut(stride).enumerate().for_each(|(r, row)| {
+		let i = dtop + r;
+		let (up, mid, down) = (&prev[(i - 1) * stride..], &prev[i * stride..], &prev[(i + 1) * stride..]);
+		let col = |j: usize| (up[j] as usize) << 6 | (mid[j] as usize) << 3 | down[j] as usize;
+		// keep the middle and right columns, and shift in the next column
+		let mut idx = (col(dtop - 1) << 1) & 0o666 | col(dtop);
+		for (j, x) in row.iter_mut().enumerate().skip(dtop).take(dw) {
+		    idx = (idx << 1) & 0o666 | col(j + 1);
+		    *x = m[idx];
+		}
+	    });
+
+	    bg = if bg == 0 { m[0] } else { m[511] };
+	    std::mem::swap(&mut src, &mut dst);
+	}
+
+	let pixels = Array2::from_shape_fn((h + 2 * n, w + 2 * n), |(i, j)| src[(i + 2) * stride + j + 2]);
+	InfiniteImage { pixels, background: bg }
+    }
+
+    /// Number of lit pixels, or None if infinitely many are lit.
+    pub fn lit_count(&self) -> Option<usize> {
+	if self.background == 1 {
+	    None
+	} else {
+	    Some(self.pixels.iter().filter(|x| **x == 1).count())
+	}
+    }
+}
+
+/// Set the two cells around the `h` by `w` square at (top, top) to `bg`.
+fn fill_ring(buf: &mut [u8], stride: usize, top: usize, h: usize, w: usize, bg: u8) {
+    let (left, right) = (top - 2, top + w + 2);
+    for i in [top - 2, top - 1, top + h, top + h + 1] {
+	buf[i * stride + left..i * stride + right].fill(bg);
+    }
+    for i in top..top + h {
+	for j in [top - 2, top - 1, top + w, top + w + 1] {
+	    buf[i * stride + j] = bg;
+	}
+    }
+}
+
+impl fmt::Display for InfiniteImage {
+    /// Render the window of pixels, with `#` for lit and `.` for dark.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for row in self.pixels.rows() {
+	    let line: String = row.iter().map(|x| if *x == 1 { '#' } else { '.' }).collect();
+	    writeln!(f, "{}", line)?;
+	}
+	Ok(())
+    }
+}
+
+// bit of the center cell in an enhancement index
+const CENTER: usize = 1 << 4;
+
+/// Build a 512-entry enhancement table from a cellular automaton rule.
+///
+/// Life-like rules are written `B3/S23` (Conway's Life) or `B36/S23`: a dark
+/// cell lights up if its number of lit neighbors is one of the B digits, and a
+/// lit cell stays lit if it is one of the S digits. Totalistic rules are written
+/// `T4678`: a cell is lit if the number of lit cells in its whole 3x3
+/// neighborhood is one of the digits.
+pub fn parse_rule(rule: &str) -> Result<Vec<u8>, String> {
+    let counts = |part: &str, max: u32| -> Result<Vec<u32>, String> {
+	part.chars().map(|c| match c.to_digit(10) {
+	    Some(d) if d <= max => Ok(d),
+	    _ => Err(format!("bad count '{}' in rule '{}'", c, rule))
+	}).collect()
+    };
+
+    let rule = rule.trim().to_ascii_uppercase();
+    if let Some(t) = rule.strip_prefix('T') {
+	let total = counts(t, 9)?;
+	return Ok((0..512usize).map(|s| u8::from(total.contains(&s.count_ones()))).collect());
+    }
+
+    let mut born = None;
+    let mut survive = None;
+    for part in rule.split('/') {
+	if let Some(b) = part.strip_prefix('B') {
+	    born = Some(counts(b, 8)?);
+	} else if let Some(s) = part.strip_prefix('S') {
+	    survive = Some(counts(s, 8)?);
+	} else {
+	    return Err(format!("bad rule '{}', expected e.g. B3/S23 or T4678", rule));
+	}
+    }
+    let (Some(born), Some(survive)) = (born, survive) else {
+	return Err(format!("rule '{}' needs both B and S parts", rule));
+    };
+    Ok((0..512usize).map(|s| {
+	let neighbors = (s & !CENTER).count_ones();
+	let alive = if s & CENTER != 0 { survive.contains(&neighbors) } else { born.contains(&neighbors) };
+	u8::from(alive)
+    }).collect())
+}
+
+/// Parse the enhancement algorithm and the input image.
+pub fn parse_input(lines: &[String]) -> (Vec<u8>, InfiniteImage) {
+    let m: Vec<u8> = lines[0].as_bytes().iter().map(to_num).collect();
+
+    let width = lines[1].len();
+    let height = lines.len() - 1;
+
+    let mut img = Array2::zeros((height, width));
+    for (i, row) in lines[1..].iter().enumerate() {
+	for (j, c) in row.bytes().enumerate() {
+	    img[(i, j)] = to_num(&c);
+	}
+    }
+    (m, InfiniteImage::new(img))
+}
+
+pub fn day20() {
+    let lines = read_lines("input/day20.txt", true).unwrap();
+    let (m, mut img) = parse_input(&lines);
+
+    img = img.enhance_n(&m, 2);
+    println!("{}", img.lit_count().unwrap());
+
+    img = img.enhance_n(&m, 48);
+    println!("{}", img.lit_count().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_enhance_n(m: &[u8], img: &InfiniteImage) {
+	let mut slow = img.clone();
+	for n in 0..12 {
+	    assert_eq!(img.enhance_n(m, n), slow, "enhance_n({}) differs from repeated enhance", n);
+	    slow = slow.enhance(m);
+	}
+    }
+
+    #[test]
+    fn enhance_n_matches_enhance() {
+	for path in ["input/day20ex.txt", "input/day20.txt"] {
+	    let (m, img) = parse_input(&read_lines(path, true).unwrap());
+	    let light = InfiniteImage { background: 1, ..img.clone() };
+	    // the table as given, one that flashes the background, and one that keeps it lit
+	    let mut flash = m.clone();
+	    (flash[0], flash[511]) = (1, 0);
+	    let mut lit = m.clone();
+	    (lit[0], lit[511]) = (1, 1);
+	    for table in [&m, &flash, &lit] {
+		check_enhance_n(table, &img);
+		check_enhance_n(table, &light);
+	    }
+	}
+    }
+}
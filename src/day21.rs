@@ -1,78 +1,416 @@
+use crate::read_lines;
+use ndarray as nd;
+use nd::prelude::*;
 use std::collections::HashMap;
 
+/// A number of universes. These grow quickly with the target score, the
+/// number of players and the sides of the die.
+pub type Universes = u128;
+
+fn too_many_universes() -> String {
+    "too many universes to count".to_string()
+}
+
+/// The rules of a game of Dirac Dice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceGameConfig {
+    /// Spaces on the circular board, numbered from 1.
+    pub board_size: usize,
+    /// Times the die is rolled each turn.
+    pub rolls_per_turn: usize,
+    /// Sides of the deterministic die, which rolls 1, 2, 3, ... in order.
+    pub deterministic_sides: usize,
+    /// Score needed to win with the deterministic die.
+    pub deterministic_target: usize,
+    /// Sides of the Dirac die.
+    pub dirac_sides: usize,
+    /// Score needed to win with the Dirac die.
+    pub dirac_target: usize,
+}
+
+impl Default for DiceGameConfig {
+    fn default() -> Self {
+	DiceGameConfig {
+	    board_size: 10,
+	    rolls_per_turn: 3,
+	    deterministic_sides: 100,
+	    deterministic_target: 1000,
+	    dirac_sides: 3,
+	    dirac_target: 21,
+	}
+    }
+}
+
+impl DiceGameConfig {
+    /// Number of ways each total can be rolled in one turn with the Dirac die,
+    /// indexed by the total. Fails if the counts don't fit in `Universes`.
+    pub fn roll_frequencies(&self) -> Result<Vec<Universes>, String> {
+	let mut freq: Vec<Universes> = vec![1];
+	for _ in 0..self.rolls_per_turn {
+	    let mut next: Vec<Universes> = vec![0; freq.len() + self.dirac_sides];
+	    for (t, n) in freq.iter().enumerate() {
+		for face in 1..=self.dirac_sides {
+		    next[t + face] = next[t + face].checked_add(*n).ok_or_else(too_many_universes)?;
+		}
+	    }
+	    freq = next;
+	}
+	Ok(freq)
+    }
+
+    fn move_by(&self, p: usize, s: usize) -> usize {
+	(p + s - 1) % self.board_size + 1
+    }
+
+    fn move_back(&self, p: usize, s: usize) -> usize {
+	(p - 1 + self.board_size - s % self.board_size) % self.board_size + 1
+    }
+
+    /// Index of a two-player state in a table of `num_states`, by the position
+    /// and score of the player to move, `p` and `s`, and of the other, `q` and `r`.
+    fn state_index(&self, p: usize, s: usize, q: usize, r: usize) -> usize {
+	let (b, t) = (self.board_size, self.dirac_target);
+	(((p - 1) * t + s) * b + (q - 1)) * t + r
+    }
+
+    fn num_states(&self) -> usize {
+	(self.board_size * self.dirac_target).pow(2)
+    }
+
+    /// Check that the board, dice and targets are all at least 1.
+    pub fn validate(&self) -> Result<(), String> {
+	let fields = [("board_size", self.board_size), ("rolls_per_turn", self.rolls_per_turn),
+		      ("deterministic_sides", self.deterministic_sides), ("deterministic_target", self.deterministic_target),
+		      ("dirac_sides", self.dirac_sides), ("dirac_target", self.dirac_target)];
+	match fields.iter().find(|(_, v)| *v == 0) {
+	    Some((name, _)) => Err(format!("{} must be at least 1", name)),
+	    None => Ok(())
+	}
+    }
+
+    /// Check the config, and that every starting position is on the board.
+    fn check_start(&self, start: &[usize]) -> Result<(), String> {
+	self.validate()?;
+	if start.is_empty() {
+	    return Err("need at least one player".to_string());
+	}
+	match start.iter().find(|p| !(1..=self.board_size).contains(*p)) {
+	    Some(p) => Err(format!("starting position {} is not between 1 and {}", p, self.board_size)),
+	    None => Ok(())
+	}
+    }
+}
+
 #[derive(PartialEq, Hash, Clone, Eq)]
 struct State {
-    p: [usize; 2],
-    score: [usize; 2],
+    p: Vec<usize>,
+    score: Vec<usize>,
     i: usize
 }
 
-static DIE: [usize; 10] = [0, 0, 0, 1, 3, 6, 7, 6, 3, 1];
-
 impl State {
-    fn new() -> State {
-	State { p: [4, 7], score: [0, 0], i: 0}
+    fn new(start: &[usize]) -> State {
+	State { p: start.to_vec(), score: vec![0; start.len()], i: 0}
     }
 
-    fn advance(&self) -> HashMap<State, usize> {
+    fn advance(&self, config: &DiceGameConfig, die: &[Universes]) -> Result<HashMap<State, Universes>, String> {
 	let mut states = HashMap::new();
-	for s in 3..=9 {
+	let n = self.p.len();
+	for (s, f) in die.iter().enumerate().filter(|(_, f)| **f > 0) {
 	    let mut p = self.p.clone();
 	    let i = self.i;
 	    let mut score = self.score.clone();
 
-	    p[i%2] = (p[i%2] + s - 1) % 10 + 1;
-	    score[i%2] += p[i%2];
+	    p[i%n] = config.move_by(p[i%n], s);
+	    score[i%n] += p[i%n];
 
 	    let new_state = State {p, score, i: i + 1};
-	    *states.entry(new_state).or_default() += DIE[s];
+	    let count: &mut Universes = states.entry(new_state).or_default();
+	    *count = count.checked_add(*f).ok_or_else(too_many_universes)?;
 	}
-	states
+	Ok(states)
     }
 }
 
-pub fn day21() {
+/// The end of a game played with the deterministic die.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// Index of the winning player.
+    pub winner: usize,
+    pub scores: Vec<usize>,
+    pub rolls: usize,
+}
 
-    let mut p = [4, 7];
+impl Outcome {
+    /// The lowest losing score times the number of rolls.
+    pub fn answer(&self) -> usize {
+	let loser = self.scores.iter().enumerate()
+	    .filter(|(i, _)| *i != self.winner)
+	    .map(|(_, s)| *s)
+	    .min()
+	    .unwrap_or(0);
+	loser * self.rolls
+    }
+}
 
-    let mut score = [0, 0];
+/// Parse the `Player N starting position: K` lines, for players 1 to N.
+pub fn parse_start(lines: &[String]) -> Result<Vec<usize>, String> {
+    let mut start = vec![];
+    for line in lines.iter().filter(|x| !x.trim().is_empty()) {
+	let bad = || format!("bad line '{}'", line);
+	let (player, pos) = line.trim().strip_prefix("Player ")
+	    .and_then(|x| x.split_once(" starting position: "))
+	    .ok_or_else(bad)?;
+	let player: usize = player.parse().map_err(|_| bad())?;
+	let pos: usize = pos.trim().parse().map_err(|_| bad())?;
+	if player == 0 || pos == 0 {
+	    return Err(bad());
+	}
+	if start.len() < player {
+	    start.resize(player, None);
+	}
+	start[player - 1] = Some(pos);
+    }
+    let start: Option<Vec<usize>> = start.into_iter().collect();
+    match start {
+	Some(s) if !s.is_empty() => Ok(s),
+	_ => Err("missing a starting position".to_string())
+    }
+}
+
+/// Play the standard game with the deterministic die.
+/// Panics if a starting position is off the board.
+pub fn deterministic_game(start: [usize; 2]) -> Outcome {
+    deterministic_game_with(&DiceGameConfig::default(), &start).unwrap()
+}
+
+/// Play with the deterministic die until someone reaches the target score.
+/// Fails if the config is invalid or a starting position is off the board.
+pub fn deterministic_game_with(config: &DiceGameConfig, start: &[usize]) -> Result<Outcome, String> {
+    config.check_start(start)?;
+    let n = start.len();
+    let mut p = start.to_vec();
+
+    let mut score = vec![0; n];
 
     let mut i = 0;
     let mut d = 1;
     loop {
-	let s = d + ((d % 100) + 1) + (((d+1) % 100) + 1);
-	d = ((d + 2) % 100) + 1;
+	let mut s = 0;
+	for _ in 0..config.rolls_per_turn {
+	    s += d;
+	    d = d % config.deterministic_sides + 1;
+	}
 
-	p[i%2] = (p[i%2] + s - 1) % 10 + 1;
-	score[i%2] += p[i%2];
-	if score[i%2] >= 1000 {
-	    println!("{}", (i+1) * 3 * score[(i+1) % 2]);
-	    break;
+	p[i%n] = config.move_by(p[i%n], s);
+	score[i%n] += p[i%n];
+	if score[i%n] >= config.deterministic_target {
+	    return Ok(Outcome { winner: i%n, scores: score, rolls: (i+1) * config.rolls_per_turn });
 	}
 
 	i += 1;
     }
+}
+
+/// Number of universes in which each player wins the standard game with the Dirac die.
+/// Panics if a starting position is off the board.
+pub fn dirac_game(start: [usize; 2]) -> [u64; 2] {
+    let wins = dirac_game_with(&DiceGameConfig::default(), &start).unwrap();
+    [0, 1].map(|i| u64::try_from(wins[i]).expect("the standard game fits in u64"))
+}
+
+/// Number of universes in which each player wins with the Dirac die.
+/// Fails if the config is invalid, a starting position is off the board, or
+/// the counts don't fit in `Universes`.
+pub fn dirac_game_with(config: &DiceGameConfig, start: &[usize]) -> Result<Vec<Universes>, String> {
+    config.check_start(start)?;
+    let die = config.roll_frequencies()?;
+    let mut states: HashMap<State, Universes> = HashMap::new();
+    states.insert(State::new(start), 1);
+    let mut wins: Vec<Universes> = vec![0; start.len()];
+
+    while !states.is_empty() {
+	let mut new_states: HashMap<State, Universes> = HashMap::new();
+	for (s, v) in states.iter() {
+	    for (x, y) in s.advance(config, &die)? {
+		let n = v.checked_mul(y).ok_or_else(too_many_universes)?;
+		// only the player who just moved can have won
+		let mover = s.i % start.len();
+		let count = if x.score[mover] >= config.dirac_target {
+		    &mut wins[mover]
+		} else {
+		    new_states.entry(x).or_default()
+		};
+		*count = count.checked_add(n).ok_or_else(too_many_universes)?;
+	    }
+	}
+	states = new_states;
+    }
+    Ok(wins)
+}
 
-    {
-	let mut states = HashMap::new();
-	states.insert(State::new(), 1);
-	let mut wins = [0, 0];
-
-	while states.len() > 0 {
-	    let mut new_states = HashMap::new();
-	    for (s, v) in states.iter() {
-		for (x, y) in s.advance() {
-		    if x.score[0] >= 21 {
-			wins[0] += v*y;
-		    } else if x.score[1] >= 21 {
-			wins[1] += v*y;
-		    } else {
-			*new_states.entry(x).or_default() += v*y;
+/// Two-player Dirac Dice win counts for every position and score, so that
+/// any starting pair can be looked up directly.
+pub struct DiracTable {
+    config: DiceGameConfig,
+    // universes won by the player to move and the other player, indexed by
+    // `DiceGameConfig::state_index`
+    wins: Vec<Option<[Universes; 2]>>,
+}
+
+impl DiracTable {
+    /// Fails if the config is invalid or the counts don't fit in `Universes`.
+    pub fn new(config: &DiceGameConfig) -> Result<DiracTable, String> {
+	config.validate()?;
+	let (b, t) = (config.board_size, config.dirac_target);
+	let mut table = DiracTable { config: config.clone(), wins: vec![None; config.num_states()] };
+	let die = config.roll_frequencies()?;
+	for p in 1..=b {
+	    for s in 0..t {
+		for q in 1..=b {
+		    for r in 0..t {
+			table.solve(&die, p, s, q, r)?;
+		    }
+		}
+	    }
+	}
+	Ok(table)
+    }
+
+    // Universes won by the player to move, at `p` with score `s`, and by the
+    // other player, at `q` with score `r`.
+    fn solve(&mut self, die: &[Universes], p: usize, s: usize, q: usize, r: usize) -> Result<[Universes; 2], String> {
+	let idx = self.config.state_index(p, s, q, r);
+	if let Some(w) = self.wins[idx] {
+	    return Ok(w);
+	}
+	let add = |x: Universes, f: Universes, n: Universes| {
+	    f.checked_mul(n).and_then(|y| x.checked_add(y)).ok_or_else(too_many_universes)
+	};
+	let mut w = [0, 0];
+	for (roll, f) in die.iter().enumerate().filter(|(_, f)| **f > 0) {
+	    let np = self.config.move_by(p, roll);
+	    if s + np >= self.config.dirac_target {
+		w[0] = add(w[0], *f, 1)?;
+	    } else {
+		// the other player moves next
+		let [a, b] = self.solve(die, q, r, np, s + np)?;
+		w[0] = add(w[0], *f, b)?;
+		w[1] = add(w[1], *f, a)?;
+	    }
+	}
+	self.wins[idx] = Some(w);
+	Ok(w)
+    }
+
+    /// Universes in which each player wins from the given starting positions.
+    pub fn wins(&self, start: [usize; 2]) -> Result<[Universes; 2], String> {
+	self.config.check_start(&start)?;
+	Ok(self.wins[self.config.state_index(start[0], 0, start[1], 0)].unwrap())
+    }
+
+    /// Fraction of universes won by player 1, by player 1's starting position
+    /// (row) and player 2's (column), both counted from 0.
+    pub fn win_ratios(&self) -> Array2<f64> {
+	let b = self.config.board_size;
+	Array2::from_shape_fn((b, b), |(i, j)| {
+	    let [w1, w2] = self.wins([i + 1, j + 1]).unwrap();
+	    w1 as f64 / (w1 + w2) as f64
+	})
+    }
+}
+
+/// A choice made by a player after seeing their roll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Move {
+    Forward,
+    Backward,
+}
+
+/// Two-player Dirac Dice where, after each roll, the player chooses to move
+/// forward or backward by the total. Solved by expectimax: each player picks
+/// the move that maximizes their own chance of winning, and rolls are
+/// weighted by how many universes produce them.
+pub struct StrategyTable {
+    config: DiceGameConfig,
+    // probability of each roll total
+    die: Vec<f64>,
+    // probability the player to move wins, indexed by `DiceGameConfig::state_index`
+    win_prob: Vec<f64>,
+}
+
+impl StrategyTable {
+    /// Fails if the config is invalid or the roll frequencies don't fit in `Universes`.
+    pub fn new(config: &DiceGameConfig) -> Result<StrategyTable, String> {
+	config.validate()?;
+	let (b, t) = (config.board_size, config.dirac_target);
+	let freq = config.roll_frequencies()?;
+	let total: f64 = freq.iter().map(|f| *f as f64).sum();
+	let mut table = StrategyTable {
+	    config: config.clone(),
+	    die: freq.iter().map(|f| *f as f64 / total).collect(),
+	    win_prob: vec![0.0; config.num_states()],
+	};
+
+	// every move adds to a score, so fill in order of decreasing total score
+	for sum in (0..2 * t - 1).rev() {
+	    for s in sum.saturating_sub(t - 1)..=std::cmp::min(sum, t - 1) {
+		let r = sum - s;
+		for p in 1..=b {
+		    for q in 1..=b {
+			let v = (0..table.die.len())
+			    .map(|roll| table.die[roll] * table.best_move([p, q], [s, r], roll).1)
+			    .sum();
+			let idx = config.state_index(p, s, q, r);
+			table.win_prob[idx] = v;
 		    }
 		}
 	    }
-	    states = new_states;
 	}
-	println!("{:?}", std::cmp::max(wins[0], wins[1]));
+	Ok(table)
     }
+
+    /// The best move for the player to move, at `pos[0]` with `score[0]`,
+    /// after rolling `roll`, against the other player at `pos[1]` with
+    /// `score[1]`. Return it with the resulting chance of winning.
+    pub fn best_move(&self, pos: [usize; 2], score: [usize; 2], roll: usize) -> (Move, f64) {
+	let value = |np: usize| {
+	    if score[0] + np >= self.config.dirac_target {
+		1.0
+	    } else {
+		1.0 - self.win_prob[self.config.state_index(pos[1], score[1], np, score[0] + np)]
+	    }
+	};
+	let forward = value(self.config.move_by(pos[0], roll));
+	let backward = value(self.config.move_back(pos[0], roll));
+	if backward > forward {
+	    (Move::Backward, backward)
+	} else {
+	    (Move::Forward, forward)
+	}
+    }
+
+    /// Chance of player 1 winning from the given start, with both playing optimally.
+    pub fn win_probability(&self, start: [usize; 2]) -> Result<f64, String> {
+	self.config.check_start(&start)?;
+	Ok(self.win_prob[self.config.state_index(start[0], 0, start[1], 0)])
+    }
+}
+
+/// Solve both parts from the given starting positions.
+pub fn play(config: &DiceGameConfig, start: &[usize]) {
+    match deterministic_game_with(config, start) {
+	Ok(outcome) => println!("{}", outcome.answer()),
+	Err(e) => eprintln!("{}", e)
+    }
+
+    match dirac_game_with(config, start) {
+	Ok(wins) => println!("{}", wins.iter().max().unwrap()),
+	Err(e) => eprintln!("{}", e)
+    }
+}
+
+pub fn day21() {
+    let lines = read_lines("input/day21.txt", true).unwrap();
+    play(&DiceGameConfig::default(), &parse_start(&lines).unwrap());
 }
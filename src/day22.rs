@@ -0,0 +1,374 @@
+use crate::read_lines;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// An axis-aligned box in `N` dimensions, with inclusive ranges on each axis.
+#[derive(Clone, Hash, PartialEq, Eq, Debug)]
+pub struct Cuboid<const N: usize = 3> {
+    pub dims: [(i64, i64); N],
+}
+
+pub type Cube = Cuboid<3>;
+
+impl<const N: usize> Cuboid<N> {
+    pub fn intersect(&self, c: &Cuboid<N>) -> Option<Cuboid<N>> {
+	if (0..N).any(|i| self.dims[i].0 > c.dims[i].1 || c.dims[i].0 > self.dims[i].1) {
+	    return None;
+	}
+	Some(Cuboid { dims: std::array::from_fn(|i| (std::cmp::max(self.dims[i].0, c.dims[i].0),
+						     std::cmp::min(self.dims[i].1, c.dims[i].1))) })
+    }
+
+    /// Split the part of this cuboid outside `c` into at most `2N` disjoint cuboids.
+    pub fn subtract(&self, c: &Cuboid<N>) -> Vec<Cuboid<N>> {
+	let Some(ise) = self.intersect(c) else {
+	    return vec![self.clone()];
+	};
+	// cut off the slabs either side of the intersection, one axis at a time
+	let mut rest = self.clone();
+	let mut cuboids = vec![];
+	for i in 0..N {
+	    if rest.dims[i].0 < ise.dims[i].0 {
+		let mut slab = rest.clone();
+		slab.dims[i].1 = ise.dims[i].0 - 1;
+		cuboids.push(slab);
+	    }
+	    if ise.dims[i].1 < rest.dims[i].1 {
+		let mut slab = rest.clone();
+		slab.dims[i].0 = ise.dims[i].1 + 1;
+		cuboids.push(slab);
+	    }
+	    rest.dims[i] = ise.dims[i];
+	}
+	cuboids
+    }
+
+    pub fn contains(&self, p: [i64; N]) -> bool {
+	self.dims.iter().zip(p).all(|((a, b), x)| (*a..=*b).contains(&x))
+    }
+
+    /// Whether `c` lies entirely inside this cuboid.
+    pub fn contains_cuboid(&self, c: &Cuboid<N>) -> bool {
+	self.dims.iter().zip(&c.dims).all(|((a, b), (x, y))| a <= x && y <= b)
+    }
+
+    /// `None` if the volume doesn't fit in a `u128`.
+    pub fn volume(&self) -> Option<u128> {
+	self.dims.iter()
+	    .map(|(x, y)| (*y as i128 - *x as i128 + 1) as u128)
+	    .try_fold(1u128, |v, l| v.checked_mul(l))
+    }
+
+    /// Volume covered by any of the cuboids, counting overlaps once.
+    /// `None` if it doesn't fit in a `u128`.
+    pub fn union_volume(cuboids: &[Cuboid<N>]) -> Option<u128> {
+	let mut disjoint: Vec<Cuboid<N>> = vec![];
+	for c in cuboids {
+	    disjoint = disjoint.iter().flat_map(|d| d.subtract(c)).collect();
+	    disjoint.push(c.clone());
+	}
+	disjoint.iter().try_fold(0u128, |v, c| v.checked_add(c.volume()?))
+    }
+}
+
+/// A reboot step: turn every cube in a cuboid on or off.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RebootStep {
+    pub on: bool,
+    pub cube: Cube,
+}
+
+impl FromStr for RebootStep {
+    type Err = String;
+
+    /// Parse e.g. `on x=10..12,y=10..12,z=10..12`. Axes may come in any order,
+    /// ranges may be reversed, and whitespace around tokens is ignored.
+    fn from_str(l: &str) -> Result<RebootStep, String> {
+	let l = l.trim();
+	let (state, ranges) = l.split_once(char::is_whitespace)
+	    .ok_or_else(|| format!("expected a state and ranges in '{}'", l))?;
+	let on = match state {
+	    "on" => true,
+	    "off" => false,
+	    _ => return Err(format!("expected 'on' or 'off', found '{}'", state))
+	};
+
+	let mut dims = [None; 3];
+	for r in ranges.split(',') {
+	    let (axis, range) = r.split_once('=').ok_or_else(|| format!("expected 'axis=range', found '{}'", r.trim()))?;
+	    let i = match axis.trim() {
+		"x" => 0,
+		"y" => 1,
+		"z" => 2,
+		a => return Err(format!("unknown axis '{}'", a))
+	    };
+	    if dims[i].is_some() {
+		return Err(format!("axis '{}' given twice", axis.trim()));
+	    }
+	    let (a, b) = range.split_once("..").ok_or_else(|| format!("expected 'from..to', found '{}'", range.trim()))?;
+	    let parse = |x: &str| x.trim().parse::<i64>()
+		.map_err(|e| format!("bad coordinate '{}': {}", x.trim(), e));
+	    let (a, b) = (parse(a)?, parse(b)?);
+	    dims[i] = Some((a.min(b), a.max(b)));
+	}
+
+	match dims {
+	    [Some(x), Some(y), Some(z)] => Ok(RebootStep { on, cube: Cube { dims: [x, y, z] } }),
+	    _ => Err(format!("expected ranges for x, y and z in '{}'", l))
+	}
+    }
+}
+
+/// The state of the reactor, kept as a list of disjoint cuboids that are on.
+#[derive(Clone, Debug, Default)]
+pub struct Reactor {
+    cubes: Vec<Cube>,
+}
+
+impl Reactor {
+    pub fn new() -> Reactor {
+	Reactor::default()
+    }
+
+    pub fn apply(&mut self, step: &RebootStep) {
+	let mut new_cubes: Vec<Cube> = self.cubes.iter().flat_map(|oc| oc.subtract(&step.cube)).collect();
+	if step.on {
+	    new_cubes.push(step.cube.clone());
+	}
+	self.cubes = new_cubes;
+    }
+
+    /// Number of cubes that are on, or `None` if it doesn't fit in a `u128`.
+    pub fn volume(&self) -> Option<u128> {
+	self.cubes.iter().try_fold(0u128, |v, c| v.checked_add(c.volume()?))
+    }
+
+    pub fn is_on(&self, x: i64, y: i64, z: i64) -> bool {
+	self.cubes.iter().any(|c| c.contains([x, y, z]))
+    }
+
+    /// Number of cubes inside `region` that are on, or `None` if it doesn't
+    /// fit in a `u128`.
+    pub fn volume_in(&self, region: &Cube) -> Option<u128> {
+	self.cubes.iter().filter_map(|c| c.intersect(region)).try_fold(0u128, |v, c| v.checked_add(c.volume()?))
+    }
+}
+
+/// An algorithm for counting the cubes left on after a sequence of steps.
+pub trait RebootSolver {
+    fn name(&self) -> &'static str;
+
+    /// `None` if the volume of any step, or the count itself, doesn't fit
+    /// in a `u128`. Every solver gives `None` for the same steps.
+    fn volume(&self, steps: &[RebootStep]) -> Option<u128>;
+}
+
+/// Whether every step's cuboid has a volume that fits in a `u128`, which the
+/// solvers check up front so that they agree on which steps they can count.
+fn step_volumes_fit(steps: &[RebootStep]) -> bool {
+    steps.iter().all(|s| s.cube.volume().is_some())
+}
+
+/// Keep disjoint on-cuboids, splitting them around every step.
+pub struct Partition;
+
+/// Keep signed cuboids: every step cancels its overlap with each earlier
+/// cuboid, and on-steps then add themselves.
+pub struct InclusionExclusion;
+
+/// Sweep each axis in turn, compressed to the boundaries of the steps that
+/// cover the current slab. A cell is on iff the last step covering it is.
+pub struct Compression;
+
+pub const SOLVERS: [&dyn RebootSolver; 3] = [&Partition, &InclusionExclusion, &Compression];
+
+impl RebootSolver for Partition {
+    fn name(&self) -> &'static str {
+	"partition"
+    }
+
+    fn volume(&self, steps: &[RebootStep]) -> Option<u128> {
+	if !step_volumes_fit(steps) {
+	    return None;
+	}
+	let mut reactor = Reactor::new();
+	for s in steps {
+	    reactor.apply(s);
+	}
+	reactor.volume()
+    }
+}
+
+impl RebootSolver for InclusionExclusion {
+    fn name(&self) -> &'static str {
+	"inclusion-exclusion"
+    }
+
+    fn volume(&self, steps: &[RebootStep]) -> Option<u128> {
+	if !step_volumes_fit(steps) {
+	    return None;
+	}
+	let mut signs: HashMap<Cube, i128> = HashMap::new();
+	for s in steps {
+	    let mut update: HashMap<Cube, i128> = HashMap::new();
+	    for (c, n) in &signs {
+		if let Some(i) = s.cube.intersect(c) {
+		    *update.entry(i).or_insert(0) -= n;
+		}
+	    }
+	    if s.on {
+		*update.entry(s.cube.clone()).or_insert(0) += 1;
+	    }
+	    for (c, n) in update {
+		*signs.entry(c).or_insert(0) += n;
+	    }
+	    signs.retain(|_, n| *n != 0);
+	}
+	// the signed terms can add up to far more than a u128 holds before they
+	// cancel, so keep the exact sum as `hi * 2^64 + lo` with `lo` below 2^64
+	let (mut hi, mut lo) = (0i128, 0i128);
+	for (c, n) in &signs {
+	    let v = c.volume()?;
+	    hi = hi.checked_add(n.checked_mul((v >> 64) as i128)?)?;
+	    lo = lo.checked_add(n.checked_mul(v as u64 as i128)?)?;
+	    hi = hi.checked_add(lo >> 64)?;
+	    lo &= u64::MAX as i128;
+	}
+	if !(0..1 << 64).contains(&hi) {
+	    return None;
+	}
+	Some((hi as u128) << 64 | lo as u128)
+    }
+}
+
+impl Compression {
+    fn sweep(steps: &[&RebootStep], axis: usize) -> Option<u128> {
+	// half-open boundaries along this axis, wide enough for `i64::MAX + 1`
+	let end = |s: &RebootStep| s.cube.dims[axis].1 as i128 + 1;
+	let mut bounds: Vec<i128> = steps.iter()
+	    .flat_map(|s| [s.cube.dims[axis].0 as i128, end(s)])
+	    .collect();
+	bounds.sort_unstable();
+	bounds.dedup();
+
+	bounds.windows(2).try_fold(0u128, |v, w| {
+	    let slab: Vec<&RebootStep> = steps.iter()
+		.filter(|s| s.cube.dims[axis].0 as i128 <= w[0] && w[1] <= end(s))
+		.copied()
+		.collect();
+	    let len = (w[1] - w[0]) as u128;
+	    let n = match slab.last() {
+		Some(s) if axis == 2 && s.on => len,
+		Some(_) if axis < 2 => len.checked_mul(Self::sweep(&slab, axis + 1)?)?,
+		_ => 0,
+	    };
+	    v.checked_add(n)
+	})
+    }
+}
+
+impl RebootSolver for Compression {
+    fn name(&self) -> &'static str {
+	"compression"
+    }
+
+    fn volume(&self, steps: &[RebootStep]) -> Option<u128> {
+	if !step_volumes_fit(steps) {
+	    return None;
+	}
+	Self::sweep(&steps.iter().collect::<Vec<_>>(), 0)
+    }
+}
+
+/// Run every solver on the steps in `path`, printing each one's timing.
+/// Panics if they disagree.
+pub fn compare_solvers(path: &str) {
+    let lines = read_lines(path, true).unwrap();
+    let steps: Vec<RebootStep> = lines.iter().map(|l| l.parse().unwrap()).collect();
+
+    let results: Vec<(&str, Option<u128>, Duration)> = SOLVERS.iter().map(|s| {
+	let t = Instant::now();
+	let v = s.volume(&steps);
+	(s.name(), v, t.elapsed())
+    }).collect();
+    for (name, v, t) in &results {
+	match v {
+	    Some(v) => println!("{:>20}: {} in {:?}", name, v, t),
+	    None => println!("{:>20}: overflow in {:?}", name, t),
+	}
+    }
+    assert!(results.iter().all(|r| r.1 == results[0].1), "solvers disagree on {}", path);
+}
+
+pub fn day22() {
+    let lines = read_lines("input/day22.txt", true).unwrap();
+
+    let mut reactor = Reactor::new();
+    for l in &lines {
+	reactor.apply(&l.parse().unwrap());
+    }
+
+    println!("{}", reactor.volume_in(&Cube { dims: [(-50, 50); 3] }).unwrap());
+    println!("{}", reactor.volume().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solvers_agree_on_example() {
+	let lines = read_lines("input/day22ex.txt", true).unwrap();
+	let steps: Vec<RebootStep> = lines.iter().map(|l| l.parse().unwrap()).collect();
+	for solver in SOLVERS {
+	    assert_eq!(solver.volume(&steps), Some(2758514936282235), "{} is wrong", solver.name());
+	}
+
+	// overlapping cubes whose signed terms overflow a u128 when added up,
+	// though the answer fits
+	let big = 1i64 << 41;
+	let mut seed: u64 = 1;
+	let mut rand = |n: i64| {
+	    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+	    (seed >> 33) as i64 % n
+	};
+	for _ in 0..40 {
+	    let steps: Vec<RebootStep> = (0..16).map(|_| {
+		let dims = std::array::from_fn(|_| (-big + rand(1000), big - rand(1000)));
+		RebootStep { on: true, cube: Cube { dims } }
+	    }).collect();
+	    let expected = Partition.volume(&steps);
+	    assert!(expected.is_some());
+	    for solver in SOLVERS {
+		assert_eq!(solver.volume(&steps), expected, "{} disagrees on large cubes", solver.name());
+	    }
+	}
+    }
+
+    #[test]
+    fn solvers_handle_full_range() {
+	let parse = |ls: &[&str]| ls.iter().map(|l| l.parse().unwrap()).collect::<Vec<RebootStep>>();
+
+	let steps = parse(&["on x=-5000000000000..5000000000000,y=0..0,z=0..0"]);
+	for solver in SOLVERS {
+	    assert_eq!(solver.volume(&steps), Some(10000000000001), "{} is wrong", solver.name());
+	}
+
+	// a step whose own volume doesn't fit, even though it's turned off again
+	let all = format!("x={}..{},y={0}..{1},z={0}..{1}", i64::MIN, i64::MAX);
+	let steps = parse(&[&format!("on {}", all), &format!("off {}", all)]);
+	for solver in SOLVERS {
+	    assert_eq!(solver.volume(&steps), None, "{} should overflow", solver.name());
+	}
+
+	// disjoint steps that each fit, but not all together
+	let side = 1i64 << 42;
+	let steps: Vec<RebootStep> = (0..5).map(|i| {
+	    RebootStep { on: true, cube: Cube { dims: [(i * side, (i + 1) * side - 1), (0, side - 1), (0, side - 1)] } }
+	}).collect();
+	for solver in SOLVERS {
+	    assert_eq!(solver.volume(&steps), None, "{} should overflow", solver.name());
+	}
+    }
+}
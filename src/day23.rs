@@ -1,10 +1,13 @@
+use crate::read_lines;
 use std::collections::{HashMap, HashSet};
 use std::cmp::Reverse;
 
 const ALLOWED_POSITIONS: [u8; 7] = [0, 1, 3, 5, 7, 9, 10];
 
+/// A place in the burrow: a room by its hallway column and rank from the top,
+/// or a hallway column.
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
-enum Pos {
+pub enum Pos {
     Room(u8, u8),
     Hallway(u8)
 }
@@ -27,11 +30,15 @@ impl Pos {
     }
 }
 
-type Amph = (u8, Pos);
+/// An amphipod type (2, 4, 6 or 8 for A to D, the column of its room) and its position.
+pub type Amph = (u8, Pos);
+
+/// A move of an amphipod type from one position to another, with its cost.
+pub type Move = (u8, Pos, Pos, u32);
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
-struct Amphs {
-    locs: Vec<Amph>,
+pub struct Amphs {
+    pub locs: Vec<Amph>,
 }
 
 pub fn ordered<T: Ord>(a: T, b: T) -> (T, T) {
@@ -159,15 +166,14 @@ impl Amphs {
 	    print!("{}", self.character(Pos::Hallway(i)));
 	}
 	println!("#");
-	print!("###");
 	for r in 0..max_rank {
-	    for i in 2..10 {
+	    print!("{}", if r == 0 { "###" } else { "  #" });
+	    for i in 2..9 {
 		print!("{}", self.character(Pos::Room(i, r)));
 	    }
-	    println!("##");
-	    print!("  #");
+	    println!("{}", if r == 0 { "###" } else { "#" });
 	}
-	println!("  #########  ");
+	println!("  #########");
     }
 
     /// Distance heurisic.
@@ -234,6 +240,26 @@ impl Amphs {
 	    _ => unreachable!("bad target")
 	}
     }
+    /// Number of ranks in each room.
+    pub fn depth(&self) -> u8 {
+	(self.locs.len() / 4) as u8
+    }
+
+    /// Insert the two folded-up ranks, `DCBA` and `DBAC`, below the first.
+    pub fn unfold(&self) -> Amphs {
+	use Pos::*;
+	let mut locs: Vec<Amph> = self.locs.iter().map(|&(t, p)| match p {
+	    Room(x, rank) if rank >= 1 => (t, Room(x, rank + 2)),
+	    _ => (t, p)
+	}).collect();
+	for (rank, row) in [(1, [8, 6, 4, 2]), (2, [8, 4, 2, 6])] {
+	    for (x, t) in [2, 4, 6, 8].into_iter().zip(row) {
+		locs.push((t, Room(x, rank)));
+	    }
+	}
+	Amphs { locs }
+    }
+
     pub fn done(&self) -> bool {
 	self.locs.iter().all(|(target, pos)| {
 	    match pos {
@@ -244,24 +270,57 @@ impl Amphs {
     }
 }
 
+/// Parse a burrow diagram. The second line is the hallway, and each line
+/// after it is one rank of the rooms, from the top.
+pub fn parse_burrow(lines: &[String]) -> Result<Amphs, String> {
+    use Pos::*;
+    let mut locs = vec![];
+    for (row, l) in lines.iter().enumerate().skip(1) {
+	for (col, c) in l.chars().enumerate() {
+	    let t = match c {
+		'A'..='D' => 2 * (c as u8 - b'A' + 1),
+		'.' | '#' | ' ' => continue,
+		_ => return Err(format!("unexpected '{}' on line {}", c, row + 1))
+	    };
+	    let x = (col as u8).wrapping_sub(1);
+	    let pos = if row == 1 { Hallway(x) } else { Room(x, row as u8 - 2) };
+	    let valid = match pos {
+		Hallway(x) => ALLOWED_POSITIONS.contains(&x),
+		Room(x, _) => [2, 4, 6, 8].contains(&x)
+	    };
+	    if !valid {
+		return Err(format!("amphipod '{}' outside the burrow on line {}", c, row + 1));
+	    }
+	    locs.push((t, pos));
+	}
+    }
+
+    let amphs = Amphs { locs };
+    for t in [2, 4, 6, 8] {
+	if amphs.locs.iter().filter(|x| x.0 == t).count() != amphs.locs.len() / 4 || amphs.locs.is_empty() {
+	    return Err("expected the same number of each type of amphipod".to_string());
+	}
+    }
+    Ok(amphs)
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 struct Node {
     cost_and_heur: u32,
     cost: u32,
+    moves: Vec<Move>,
     amphs: Amphs
 }
 
 impl Node {
     fn next(&self, i: usize, next_pos: Pos, max_rank: u8) -> Self {
-	// let pos = self.amphs.locs[i].1;
-	// let target = self.amphs.locs[i].0;
+	let (target, pos) = self.amphs.locs[i];
 	let (m, c) = self.amphs.move_amph(i, next_pos);
-	//let next_move = (target, pos, next_pos);
-	//let mut new_moves = self.moves.clone();
-	//new_moves.push(next_move);
+	let mut new_moves = self.moves.clone();
+	new_moves.push((target, pos, next_pos, c));
 	Node { cost_and_heur: c + self.cost + m.dist_heuristic(max_rank),
 	       cost: c + self.cost,
-	       //moves: new_moves,
+	       moves: new_moves,
 	       amphs: m }
     }
 }
@@ -278,11 +337,12 @@ impl Ord for Node {
     }
 }
 
-fn day23_solve(a: &Amphs, max_rank: u8) {
+/// Find the cheapest way to organize the amphipods, returning its cost and moves.
+pub fn day23_solve(a: &Amphs, max_rank: u8) -> Option<(u32, Vec<Move>)> {
     use Pos::*;
     let amphipods = Node {cost_and_heur: 0,
 			  cost: 0,
-			  //moves: vec![],
+			  moves: vec![],
 			  amphs: a.clone()};
 
 
@@ -299,8 +359,7 @@ fn day23_solve(a: &Amphs, max_rank: u8) {
 	}
 
 	if node.amphs.done() {
-	    println!("{}", node.cost);
-	    break;
+	    return Some((node.cost, node.moves));
 	}
 
 	visited.insert(node.amphs.clone(), node.cost);
@@ -366,37 +425,40 @@ fn day23_solve(a: &Amphs, max_rank: u8) {
 	    }
 	}
     }
+    None
+}
+
+/// Print each burrow on the way from `a` by the given moves.
+pub fn replay(a: &Amphs, moves: &[Move]) {
+    let depth = a.depth();
+    let mut a = a.clone();
+    a.display(depth);
+    for (t, from, to, cost) in moves {
+	let i = a.locs.iter().position(|x| x.0 == *t && x.1 == *from).expect("no such amphipod to move");
+	a = a.move_amph(i, *to).0;
+	println!();
+	println!("{} {:?} -> {:?}: {}", a.character(*to), from, to, cost);
+	a.display(depth);
+    }
+}
+
+/// Solve both parts for the burrow in `path`, replaying the moves of each.
+pub fn replay_solutions(path: &str) {
+    let lines = read_lines(path, true).unwrap();
+    let amphs = parse_burrow(&lines).unwrap();
+    for a in [amphs.clone(), amphs.unfold()] {
+	let (cost, moves) = day23_solve(&a, a.depth()).expect("no solution");
+	replay(&a, &moves);
+	println!("total cost {}", cost);
+	println!();
+    }
 }
 
 pub fn day23() {
-    use Pos::*;
-    let amphs = Amphs {locs: vec![(2, Room(6, 1)),
-				  (2, Room(8, 1)),
-				  (8, Room(6, 0)),
-				  (8, Room(8, 0)),
-				  (4, Room(2, 1)),
-				  (4, Room(4, 0)),
-				  (6, Room(2, 0)),
-				  (6, Room(4, 1))]};
-    day23_solve(&amphs, 2);
-
-    let amphs = Amphs {locs: vec![(2, Room(6, 3)),
-				  (2, Room(8, 3)),
-				  (8, Room(6, 0)),
-				  (8, Room(8, 0)),
-				  (4, Room(2, 3)),
-				  (4, Room(4, 0)),
-				  (6, Room(2, 0)),
-				  (6, Room(4, 3)),
-
-				  (8, Room(2, 1)),
-				  (8, Room(2, 2)),
-				  (6, Room(4, 1)),
-				  (4, Room(4, 2)),
-				  (4, Room(6, 1)),
-				  (2, Room(6, 2)),
-				  (2, Room(8, 1)),
-				  (6, Room(8, 2))]};
-    //amphs.display(4);
-    day23_solve(&amphs, 4);
+    let lines = read_lines("input/day23.txt", true).unwrap();
+    let amphs = parse_burrow(&lines).unwrap();
+    println!("{}", day23_solve(&amphs, amphs.depth()).unwrap().0);
+
+    let amphs = amphs.unfold();
+    println!("{}", day23_solve(&amphs, amphs.depth()).unwrap().0);
 }
@@ -72,6 +72,44 @@ fn day25() {
 
 
 fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+	// `day19 <ply|obj> <out-file>` exports the beacon map as a point cloud
+	Some("day19") => {
+	    let format = args.get(1).map(|x| x.parse::<aoc2021::day19::CloudFormat>());
+	    match (format, args.get(2)) {
+		(Some(Ok(format)), Some(path)) => aoc2021::day19::export_point_cloud(format, path)?,
+		(Some(Err(e)), _) => eprintln!("{}", e),
+		_ => eprintln!("usage: day19 <ply|obj> <out-file>")
+	    }
+	    return Ok(());
+	},
+	// `day21 <p1> <p2> ...` plays Dirac Dice from the given starting positions
+	Some("day21") => {
+	    let config = aoc2021::day21::DiceGameConfig::default();
+	    let start: Option<Vec<usize>> = args[1..].iter().map(|x| x.parse().ok()).collect();
+	    match start {
+		Some(s) if !s.is_empty() && s.iter().all(|p| (1..=config.board_size).contains(p)) => {
+		    aoc2021::day21::play(&config, &s)
+		},
+		_ => eprintln!("usage: day21 <p1> <p2> ..., with positions from 1 to {}", config.board_size)
+	    }
+	    return Ok(());
+	},
+	// `day22 [file]` cross-checks and times the reactor solvers, by default on the example
+	Some("day22") => {
+	    aoc2021::day22::compare_solvers(args.get(1).map_or("input/day22ex.txt", String::as_str));
+	    return Ok(());
+	},
+	// `day23 [file]` prints every step of the cheapest way to organize the amphipods
+	Some("day23") => {
+	    aoc2021::day23::replay_solutions(args.get(1).map_or("input/day23.txt", String::as_str));
+	    return Ok(());
+	},
+	_ => {}
+    }
+
     aoc2021::day01();
     aoc2021::day02();
     aoc2021::day03();